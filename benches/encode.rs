@@ -0,0 +1,103 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct A {
+    a: ((u32, u16), usize),
+    b: Option<C>,
+}
+
+#[derive(Serialize)]
+struct C(());
+
+#[derive(Serialize)]
+enum D<'a> {
+    #[allow(dead_code)]
+    A(u32),
+    B(&'a [u8]),
+}
+
+#[derive(Serialize)]
+enum V {
+    A,
+}
+
+#[derive(Serialize)]
+struct X(u128, u32, u8);
+
+#[derive(Serialize)]
+enum E {
+    S { r: u8, g: u8, b: u8 },
+}
+
+#[derive(Serialize)]
+struct B<'a> {
+    a: A,
+    b: &'a [u8],
+    c: [u32; 5],
+    d: [A; 2],
+    e: (u8, u16, u32, u64, u128),
+    e2: (i8, i16, i32, i64, i128),
+    f: D<'a>,
+    g: (bool, bool),
+    h: char,
+    i: &'a str,
+    j: V,
+    k: X,
+    l: E,
+    m: (),
+}
+
+fn monster() -> B<'static> {
+    B {
+        a: A {
+            a: ((288, 328), 3280),
+            b: Some(C(())),
+        },
+        b: &[0, 2, 3],
+        c: [0, 1, 2, 3, 4],
+        d: [
+            A {
+                a: ((2, 3), 8),
+                b: None,
+            },
+            A {
+                a: ((1, 2), 7),
+                b: Some(C(())),
+            },
+        ],
+        e: (u8::MAX, u16::MAX, u32::MAX, u64::MAX, u128::MAX),
+        e2: (i8::MIN, i16::MIN, i32::MIN, i64::MIN, i128::MIN),
+        f: D::B(&[0, 1, 89]),
+        g: (true, false),
+        h: '⚑',
+        i: "hello world",
+        j: V::A,
+        k: X(0, 1, 2),
+        l: E::S { r: 255, g: 0, b: 0 },
+        m: (),
+    }
+}
+
+fn encode(c: &mut Criterion) {
+    let value = monster();
+    let mut buf = [0u8; 256];
+
+    let mut group = c.benchmark_group("monster_struct");
+    group.bench_function("checked", |b| {
+        b.iter(|| fermion::encode(black_box(&value), &mut buf))
+    });
+    group.bench_function("presized", |b| {
+        // monster() serializes the same way every time
+        b.iter(|| unsafe {
+            fermion::encode_presized(black_box(&value), &mut buf)
+        })
+    });
+    group.bench_function("size", |b| {
+        b.iter(|| fermion::encoded_size(black_box(&value)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encode);
+criterion_main!(benches);
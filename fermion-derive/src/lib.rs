@@ -0,0 +1,223 @@
+#![deny(missing_docs)]
+//! Fermion derive
+//!
+//! `#[derive(Encode, Decode)]` for fermion, generating straight-line code
+//! that produces the same bytes as the serde path without its visitor
+//! machinery.
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields,
+    GenericParam, Generics, Index, Lifetime, LifetimeParam,
+};
+
+/// Derives `fermion::Encode`
+#[proc_macro_derive(Encode)]
+pub fn derive_encode(
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    encode(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `fermion::Decode`
+#[proc_macro_derive(Decode)]
+pub fn derive_decode(
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    decode(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn encode(input: DeriveInput) -> Result<TokenStream, Error> {
+    let name = &input.ident;
+
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::fermion::Encode));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, writes) = encode_fields(&data.fields);
+            quote! {
+                let Self #pattern = self;
+                #(#writes)*
+            }
+        }
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            for (index, variant) in data.variants.iter().enumerate() {
+                let tag = variant_tag(index, &variant.ident)?;
+                let ident = &variant.ident;
+                let (pattern, writes) = encode_fields(&variant.fields);
+                arms.push(quote! {
+                    Self::#ident #pattern => {
+                        __serializer.write_u8(#tag)?;
+                        #(#writes)*
+                    }
+                });
+            }
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "fermion does not support unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::fermion::Encode for #name #ty_generics
+        #where_clause
+        {
+            #[inline]
+            fn encode<__W: ::fermion::Writer>(
+                &self,
+                __serializer: &mut ::fermion::Serializer<__W>,
+            ) -> ::core::result::Result<(), ::fermion::Error> {
+                #body
+                ::core::result::Result::Ok(())
+            }
+        }
+    })
+}
+
+fn decode(input: DeriveInput) -> Result<TokenStream, Error> {
+    let name = &input.ident;
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    let generics = decode_generics(&input.generics);
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let value = decode_fields(&data.fields);
+            quote! {
+                ::core::result::Result::Ok(Self #value)
+            }
+        }
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            for (index, variant) in data.variants.iter().enumerate() {
+                let tag = variant_tag(index, &variant.ident)?;
+                let ident = &variant.ident;
+                let value = decode_fields(&variant.fields);
+                arms.push(quote! {
+                    #tag => ::core::result::Result::Ok(Self::#ident #value),
+                });
+            }
+            quote! {
+                match __deserializer.read_u8()? {
+                    #(#arms)*
+                    _ => ::core::result::Result::Err(
+                        ::fermion::Error::InvalidRepresentation,
+                    ),
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "fermion does not support unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::fermion::Decode<'__de> for #name #ty_generics
+        #where_clause
+        {
+            #[inline]
+            fn decode(
+                __deserializer: &mut ::fermion::Deserializer<'__de>,
+            ) -> ::core::result::Result<Self, ::fermion::Error> {
+                #body
+            }
+        }
+    })
+}
+
+/// Adds the `'__de` lifetime, outliving every lifetime of the type, and a
+/// `Decode<'__de>` bound on every type parameter.
+fn decode_generics(generics: &Generics) -> Generics {
+    let de = Lifetime::new("'__de", Span::call_site());
+    let mut de_param = LifetimeParam::new(de.clone());
+    for lifetime in generics.lifetimes() {
+        de_param.bounds.push(lifetime.lifetime.clone());
+    }
+
+    let mut generics = generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::fermion::Decode<#de>));
+    }
+    generics.params.insert(0, GenericParam::Lifetime(de_param));
+    generics
+}
+
+fn variant_tag(index: usize, ident: &syn::Ident) -> Result<u8, Error> {
+    if index > u8::MAX as usize {
+        Err(Error::new_spanned(
+            ident,
+            "fermion supports at most 256 enum variants",
+        ))
+    } else {
+        Ok(index as u8)
+    }
+}
+
+/// Returns a pattern binding every field, and the statements encoding them
+/// in declaration order.
+fn encode_fields(fields: &Fields) -> (TokenStream, Vec<TokenStream>) {
+    let bindings: Vec<_> = (0..fields.len())
+        .map(|i| format_ident!("__field{}", i))
+        .collect();
+    let writes = bindings
+        .iter()
+        .map(|binding| {
+            quote! {
+                ::fermion::Encode::encode(#binding, __serializer)?;
+            }
+        })
+        .collect();
+
+    let pattern = match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|f| &f.ident);
+            quote!({ #(#names: #bindings),* })
+        }
+        Fields::Unnamed(_) => quote!(( #(#bindings),* )),
+        Fields::Unit => quote!(),
+    };
+    (pattern, writes)
+}
+
+/// Returns the constructor arguments decoding every field in declaration
+/// order.
+fn decode_fields(fields: &Fields) -> TokenStream {
+    let read = quote! {
+        ::fermion::Decode::decode(__deserializer)?
+    };
+    match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|f| &f.ident);
+            quote!({ #(#names: #read),* })
+        }
+        Fields::Unnamed(unnamed) => {
+            let indices = (0..unnamed.unnamed.len()).map(Index::from);
+            quote!({ #(#indices: #read),* })
+        }
+        Fields::Unit => quote!(),
+    }
+}
@@ -0,0 +1,148 @@
+use core::fmt::Debug;
+
+use fermion::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Encode, Decode)]
+struct A {
+    a: ((u32, u16), usize),
+    b: Option<C>,
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Encode, Decode)]
+struct C(());
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Encode, Decode)]
+enum D<'a> {
+    A(u32),
+    B(&'a [u8]),
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Encode, Decode)]
+enum V {
+    A,
+    B,
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Encode, Decode)]
+struct X(u128, u32, u8);
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Encode, Decode)]
+enum E {
+    S { r: u8, g: u8, b: u8 },
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Encode, Decode)]
+struct B<'a> {
+    a: A,
+    b: &'a [u8],
+    c: [u32; 5],
+    d: [A; 2],
+    e: (u8, u16, u32, u64, u128),
+    e2: (i8, i16, i32, i64, i128),
+    f: D<'a>,
+    g: (bool, bool),
+    h: char,
+    i: &'a str,
+    j: V,
+    k: X,
+    l: E,
+    m: (),
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Encode, Decode)]
+struct G<T>(T, Option<T>);
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Encode, Decode)]
+struct U;
+
+/// Asserts the derived encoding matches the serde one byte for byte, and
+/// that both decoders agree on the result.
+fn assert_same<'a, T>(
+    value: &T,
+    serde_buf: &'a mut [u8],
+    derive_buf: &'a mut [u8],
+) where
+    T: Serialize + Deserialize<'a> + Encode + Decode<'a> + PartialEq + Debug,
+{
+    fermion::encode(value, serde_buf).unwrap();
+    value
+        .encode(&mut fermion::Serializer::new(derive_buf))
+        .unwrap();
+    let serde_buf: &'a [u8] = serde_buf;
+    let derive_buf: &'a [u8] = derive_buf;
+    assert_eq!(serde_buf, derive_buf);
+
+    let serde_decoded: T = fermion::decode(serde_buf).unwrap();
+    let derive_decoded =
+        T::decode(&mut fermion::Deserializer::new(derive_buf)).unwrap();
+    assert_eq!(&serde_decoded, value);
+    assert_eq!(&derive_decoded, value);
+}
+
+macro_rules! assert_same {
+    ($value:expr) => {
+        assert_same(&$value, &mut [0u8; 256], &mut [0u8; 256])
+    };
+}
+
+fn a() -> A {
+    A {
+        a: ((288, 328), 3280),
+        b: Some(C(())),
+    }
+}
+
+#[test]
+fn monster_struct() {
+    assert_same!(a());
+    assert_same!(A {
+        a: ((2, 3), 8),
+        b: None,
+    });
+    assert_same!(C(()));
+    assert_same!(D::A(7));
+    assert_same!(D::B(&[0, 1, 89]));
+    assert_same!(V::A);
+    assert_same!(V::B);
+    assert_same!(X(0, 1, 2));
+    assert_same!(E::S { r: 255, g: 0, b: 0 });
+    assert_same!(B {
+        a: a(),
+        b: &[0, 2, 3],
+        c: [0, 1, 2, 3, 4],
+        d: [
+            A {
+                a: ((2, 3), 8),
+                b: None,
+            },
+            a(),
+        ],
+        e: (u8::MAX, u16::MAX, u32::MAX, u64::MAX, u128::MAX),
+        e2: (i8::MIN, i16::MIN, i32::MIN, i64::MIN, i128::MIN),
+        f: D::B(&[0, 1, 89]),
+        g: (true, false),
+        h: '⚑',
+        i: "hello world",
+        j: V::A,
+        k: X(0, 1, 2),
+        l: E::S { r: 255, g: 0, b: 0 },
+        m: (),
+    });
+}
+
+#[test]
+fn generics() {
+    assert_same!(G(3u16, Some(4)));
+    assert_same!(G(V::B, None));
+    assert_same!(U);
+}
+
+#[test]
+fn invalid_tag() {
+    let buf = [2u8];
+    assert_eq!(
+        V::decode(&mut fermion::Deserializer::new(&buf)),
+        Err(fermion::Error::InvalidRepresentation)
+    );
+}
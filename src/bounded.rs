@@ -0,0 +1,248 @@
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+
+use serde::de::{Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use crate::max_size::MaxSize;
+use crate::{length_exceeded, Error};
+
+/// Newtype name that makes fermion use a single byte for the next length
+/// prefix. Other formats just see a newtype struct.
+pub(crate) const SHORT_PREFIX: &str = "$fermion::ShortPrefix";
+
+const fn prefix_size(n: usize) -> usize {
+    if n <= u8::MAX as usize {
+        1
+    } else {
+        2
+    }
+}
+
+/// A borrowed `str` of at most `N` bytes.
+///
+/// Longer input is rejected with `Error::LengthExceeded` both when
+/// constructing the value and when decoding it. When `N` fits in a `u8` the
+/// length prefix is encoded in a single byte.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct BoundedStr<'de, const N: usize>(&'de str);
+
+/// A borrowed byte slice of at most `N` bytes.
+///
+/// Longer input is rejected with `Error::LengthExceeded` both when
+/// constructing the value and when decoding it. When `N` fits in a `u8` the
+/// length prefix is encoded in a single byte.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct BoundedBytes<'de, const N: usize>(&'de [u8]);
+
+impl<'de, const N: usize> BoundedStr<'de, N> {
+    /// Wraps `s`, failing if it is longer than `N` bytes
+    pub fn new(s: &'de str) -> Result<Self, Error> {
+        if s.len() > N {
+            Err(Error::LengthExceeded)
+        } else {
+            Ok(BoundedStr(s))
+        }
+    }
+
+    /// Returns the wrapped `str`
+    pub fn as_str(&self) -> &'de str {
+        self.0
+    }
+}
+
+impl<'de, const N: usize> BoundedBytes<'de, N> {
+    /// Wraps `bytes`, failing if it is longer than `N` bytes
+    pub fn new(bytes: &'de [u8]) -> Result<Self, Error> {
+        if bytes.len() > N {
+            Err(Error::LengthExceeded)
+        } else {
+            Ok(BoundedBytes(bytes))
+        }
+    }
+
+    /// Returns the wrapped byte slice
+    pub fn as_bytes(&self) -> &'de [u8] {
+        self.0
+    }
+}
+
+impl<const N: usize> Deref for BoundedStr<'_, N> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.0
+    }
+}
+
+impl<const N: usize> Deref for BoundedBytes<'_, N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0
+    }
+}
+
+impl<const N: usize> MaxSize for BoundedStr<'_, N> {
+    const MAX_SIZE: usize = prefix_size(N) + N;
+}
+
+impl<const N: usize> MaxSize for BoundedBytes<'_, N> {
+    const MAX_SIZE: usize = prefix_size(N) + N;
+}
+
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl<const N: usize> Serialize for BoundedStr<'_, N> {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if prefix_size(N) == 1 {
+            serializer.serialize_newtype_struct(SHORT_PREFIX, self.0)
+        } else {
+            serializer.serialize_str(self.0)
+        }
+    }
+}
+
+impl<const N: usize> Serialize for BoundedBytes<'_, N> {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if prefix_size(N) == 1 {
+            serializer.serialize_newtype_struct(SHORT_PREFIX, &Bytes(self.0))
+        } else {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+}
+
+struct ShortPrefixVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for ShortPrefixVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a length-prefixed value")
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        T::deserialize(deserializer)
+    }
+}
+
+fn deserialize_bounded<'de, T, D, const N: usize>(
+    deserializer: D,
+) -> Result<T, D::Error>
+where
+    T: Deserialize<'de> + Deref,
+    T::Target: AsRef<[u8]>,
+    D: Deserializer<'de>,
+{
+    let value = if prefix_size(N) == 1 {
+        deserializer.deserialize_newtype_struct(
+            SHORT_PREFIX,
+            ShortPrefixVisitor(PhantomData),
+        )?
+    } else {
+        T::deserialize(deserializer)?
+    };
+    let len = value.as_ref().len();
+    if len > N {
+        Err(length_exceeded(len))
+    } else {
+        Ok(value)
+    }
+}
+
+impl<'de, const N: usize> Deserialize<'de> for BoundedStr<'de, N> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_bounded::<&'de str, D, N>(deserializer).map(BoundedStr)
+    }
+}
+
+impl<'de, const N: usize> Deserialize<'de> for BoundedBytes<'de, N> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_bounded::<&'de [u8], D, N>(deserializer).map(BoundedBytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode, encode};
+
+    #[test]
+    fn short_prefix() {
+        let orig = BoundedStr::<16>::new("sensor-1").unwrap();
+
+        let mut buf = [0u8; BoundedStr::<16>::MAX_SIZE];
+        encode(&orig, &mut buf).unwrap();
+        assert_eq!(buf[0], 8);
+        assert_eq!(&buf[1..9], b"sensor-1");
+
+        let decoded: BoundedStr<16> = decode(&buf).unwrap();
+        assert_eq!(orig, decoded);
+    }
+
+    #[test]
+    fn long_prefix() {
+        let bytes = [7u8; 300];
+        let orig = BoundedBytes::<300>::new(&bytes).unwrap();
+
+        let mut buf = [0u8; BoundedBytes::<300>::MAX_SIZE];
+        encode(&orig, &mut buf).unwrap();
+
+        // same encoding as a plain byte slice
+        let plain: &[u8] = decode(&buf).unwrap();
+        assert_eq!(plain, &bytes[..]);
+
+        let decoded: BoundedBytes<300> = decode(&buf).unwrap();
+        assert_eq!(orig, decoded);
+    }
+
+    #[test]
+    fn exceeded() {
+        assert_eq!(
+            BoundedStr::<4>::new("too long"),
+            Err(Error::LengthExceeded)
+        );
+        assert_eq!(
+            BoundedBytes::<2>::new(&[1, 2, 3]),
+            Err(Error::LengthExceeded)
+        );
+
+        let mut buf = [0u8; 16];
+        encode(&BoundedBytes::<8>::new(&[1, 2, 3]).unwrap(), &mut buf).unwrap();
+        assert_eq!(decode::<BoundedBytes<2>>(&buf), Err(Error::LengthExceeded));
+
+        let mut buf = [0u8; 512];
+        let long: &[u8] = &[0u8; 400];
+        encode(&long, &mut buf).unwrap();
+        assert_eq!(
+            decode::<BoundedBytes<300>>(&buf),
+            Err(Error::LengthExceeded)
+        );
+    }
+}
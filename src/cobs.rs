@@ -0,0 +1,421 @@
+//! COBS framing, so encoded values delimit themselves on byte streams.
+//!
+//! Consistent Overhead Byte Stuffing removes every zero byte from a frame at
+//! the cost of one byte per 254, so a zero can terminate it. Both directions
+//! work in place in the caller's buffer.
+
+use serde::{Deserialize, Serialize};
+
+use crate::hash::feed_encoded;
+use crate::{decode_whole, encode, Error};
+
+const MAX_RUN: u8 = 0xff;
+
+/// Counts the size of the frame stuffed from the bytes fed to it
+struct FrameSize {
+    size: usize,
+    // Non-zero bytes since the last code byte
+    run: usize,
+}
+
+impl Default for FrameSize {
+    fn default() -> Self {
+        // the first code byte and the terminator
+        FrameSize { size: 2, run: 0 }
+    }
+}
+
+impl FrameSize {
+    fn feed(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            // a zero byte turns into a code byte
+            self.size += 1;
+            if *byte == 0 {
+                self.run = 0;
+            } else {
+                self.run += 1;
+                if self.run == MAX_RUN as usize - 1 {
+                    self.size += 1;
+                    self.run = 0;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the size of the frame `value` encodes to
+#[cold]
+fn frame_size<T: Serialize>(value: &T) -> Result<usize, Error> {
+    let mut frame = FrameSize::default();
+    feed_encoded(value, |bytes| {
+        frame.feed(bytes);
+        Ok(())
+    })?;
+    Ok(frame.size)
+}
+
+/// Stuffs `len` bytes starting at `from` to the start of `buf`
+///
+/// Every byte is read before its position is written to, as long as `from`
+/// is at least the number of code bytes added to them.
+fn stuff(buf: &mut [u8], from: usize, len: usize) -> usize {
+    let mut code_at = 0;
+    let mut code = 1;
+    let mut out = 1;
+    for i in from..from + len {
+        let byte = buf[i];
+        if byte != 0 {
+            buf[out] = byte;
+            out += 1;
+            code += 1;
+        }
+        if byte == 0 || code == MAX_RUN {
+            buf[code_at] = code;
+            code_at = out;
+            out += 1;
+            code = 1;
+        }
+    }
+    buf[code_at] = code;
+    out
+}
+
+/// Unstuffs a frame, without its terminating zero, to the start of `buf`
+fn unstuff(buf: &mut [u8]) -> Result<usize, Error> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < buf.len() {
+        let code = buf[read];
+        let end = read + code as usize;
+        if code == 0 || end > buf.len() {
+            return Err(Error::InvalidRepresentation);
+        }
+        buf.copy_within(read + 1..end, write);
+        write += code as usize - 1;
+        read = end;
+        if code != MAX_RUN && read < buf.len() {
+            buf[write] = 0;
+            write += 1;
+        }
+    }
+    Ok(write)
+}
+
+/// Encodes a value into provided buffer as a zero-terminated COBS frame
+///
+/// Returns the size of the frame, including the terminating zero. On
+/// `Error::OutOfSpace`, `needed` is the full size of the frame.
+pub fn encode_cobs<T: Serialize>(
+    value: &T,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let available = buf.len();
+    // the value is encoded past the first code byte, leaving room for the
+    // terminator, then moved forward past the other code bytes and stuffed
+    // back from there
+    let start = available.min(1);
+    let room = available.saturating_sub(2);
+    let len = match encode(value, &mut buf[start..][..room]) {
+        Ok(len) => len,
+        Err(Error::OutOfSpace { .. }) => {
+            return Err(Error::OutOfSpace {
+                needed: frame_size(value)?,
+                available,
+            })
+        }
+        Err(e) => return Err(e),
+    };
+    let mut frame = FrameSize::default();
+    frame.feed(&buf[start..start + len]);
+    if frame.size > available {
+        return Err(Error::OutOfSpace {
+            needed: frame.size,
+            available,
+        });
+    }
+    let from = frame.size - 1 - len;
+    buf.copy_within(start..start + len, from);
+    let end = stuff(buf, from, len);
+    buf[end] = 0;
+    Ok(end + 1)
+}
+
+/// Decodes a value from a COBS frame, unstuffing it in place
+///
+/// `frame` may include its terminating zero.
+pub fn decode_cobs<'de, T: Deserialize<'de>>(
+    frame: &'de mut [u8],
+) -> Result<T, Error> {
+    let frame = match frame.last() {
+        Some(0) => {
+            let end = frame.len() - 1;
+            &mut frame[..end]
+        }
+        _ => frame,
+    };
+    let len = unstuff(frame)?;
+    decode_whole(&frame[..len])
+}
+
+/// The outcome of feeding input to a `CobsAccumulator`
+#[derive(Debug, PartialEq)]
+pub enum FeedResult<'a, T> {
+    /// All input was taken without completing a frame
+    Consumed,
+    /// A frame was completed and decoded
+    Success {
+        /// The decoded value
+        value: T,
+        /// Input following the frame
+        remaining: &'a [u8],
+    },
+    /// A frame was completed but dropped
+    ///
+    /// `Error::LengthExceeded` means it didn't fit the accumulator.
+    Error {
+        /// Why the frame was dropped
+        error: Error,
+        /// Input following the frame
+        remaining: &'a [u8],
+    },
+}
+
+/// Collects COBS frames from input arriving in arbitrary chunks
+///
+/// Frames longer than `N` bytes are dropped, and the accumulator picks up
+/// again at the next one.
+///
+/// ```
+/// use fermion::{CobsAccumulator, FeedResult};
+///
+/// let mut buf = [0u8; 16];
+/// let len = fermion::encode_cobs(&(1u8, 2u16), &mut buf).unwrap();
+///
+/// let mut accumulator = CobsAccumulator::<16>::new();
+/// let (first, second) = buf[..len].split_at(2);
+/// assert_eq!(accumulator.feed::<(u8, u16)>(first), FeedResult::Consumed);
+/// assert_eq!(
+///     accumulator.feed::<(u8, u16)>(second),
+///     FeedResult::Success {
+///         value: (1, 2),
+///         remaining: &[],
+///     }
+/// );
+/// ```
+#[derive(Debug)]
+pub struct CobsAccumulator<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    overflowed: bool,
+}
+
+impl<const N: usize> Default for CobsAccumulator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> CobsAccumulator<N> {
+    /// Creates an empty accumulator
+    pub const fn new() -> Self {
+        CobsAccumulator {
+            buf: [0; N],
+            len: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Takes input up to and including the end of the next frame
+    ///
+    /// Call again with `remaining` until it is empty. Empty frames are
+    /// skipped, so senders may precede frames with a zero to flush any
+    /// partial one.
+    pub fn feed<'de, 'a, T: Deserialize<'de>>(
+        &'de mut self,
+        mut input: &'a [u8],
+    ) -> FeedResult<'a, T> {
+        loop {
+            let (chunk, remaining) =
+                match input.iter().position(|byte| *byte == 0) {
+                    Some(end) => (&input[..end], Some(&input[end + 1..])),
+                    None => (input, None),
+                };
+            if self.len + chunk.len() > N {
+                self.overflowed = true;
+            } else if !self.overflowed {
+                self.buf[self.len..self.len + chunk.len()]
+                    .copy_from_slice(chunk);
+                self.len += chunk.len();
+            }
+
+            let remaining = match remaining {
+                Some(remaining) => remaining,
+                None => return FeedResult::Consumed,
+            };
+            let len = core::mem::replace(&mut self.len, 0);
+            if core::mem::replace(&mut self.overflowed, false) {
+                return FeedResult::Error {
+                    error: Error::LengthExceeded,
+                    remaining,
+                };
+            }
+            if len == 0 {
+                input = remaining;
+                continue;
+            }
+            return match decode_cobs(&mut self.buf[..len]) {
+                Ok(value) => FeedResult::Success { value, remaining },
+                Err(error) => FeedResult::Error { error, remaining },
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bound on the code bytes added to `len` bytes
+    fn overhead(len: usize) -> usize {
+        len / (MAX_RUN as usize - 1) + 1
+    }
+
+    type Packet<'a> = (u16, &'a str, Option<u32>);
+
+    fn packet(text: &str) -> Packet<'_> {
+        (0x0100, text, Some(0))
+    }
+
+    #[test]
+    fn round_trip() {
+        let long = core::str::from_utf8(&[b'x'; 600]).unwrap();
+        for text in ["", "a", &long[..253], &long[..254], long] {
+            let mut buf = [0xaau8; 700];
+            let len = encode_cobs(&packet(text), &mut buf).unwrap();
+
+            let mut plain = [0u8; 700];
+            let plain_len = encode(&packet(text), &mut plain).unwrap();
+            assert!(len <= plain_len + overhead(plain_len) + 1);
+            assert_eq!(buf[len - 1], 0);
+            assert!(!buf[..len - 1].contains(&0));
+
+            assert_eq!(decode_cobs(&mut buf[..len]), Ok(packet(text)));
+        }
+    }
+
+    #[test]
+    fn out_of_space() {
+        let mut buf = [0u8; 11];
+        // 10 bytes encoded, a code byte and a terminator
+        assert_eq!(
+            encode_cobs(&packet("a"), &mut buf),
+            Err(Error::OutOfSpace {
+                needed: 12,
+                available: 11
+            })
+        );
+        assert_eq!(encode_cobs(&packet("a"), &mut [0u8; 12]), Ok(12));
+        assert!(encode_cobs(&packet("a"), &mut []).is_err());
+        assert_eq!(
+            encode_cobs(&(), &mut []),
+            Err(Error::OutOfSpace {
+                needed: 2,
+                available: 0
+            })
+        );
+    }
+
+    #[test]
+    fn exact_fit() {
+        // a length prefix with a zero byte, followed by a run of text that
+        // takes a second code byte once 254 bytes long
+        let long = core::str::from_utf8(&[b'x'; 254]).unwrap();
+        for (text_len, frame_len) in
+            [(250, 254), (251, 255), (252, 256), (253, 257), (254, 259)]
+        {
+            let text = &long[..text_len];
+            let mut buf = [0u8; 260];
+            assert_eq!(
+                encode_cobs(&text, &mut buf[..frame_len - 1]),
+                Err(Error::OutOfSpace {
+                    needed: frame_len,
+                    available: frame_len - 1
+                })
+            );
+            assert_eq!(
+                encode_cobs(&text, &mut buf[..frame_len]),
+                Ok(frame_len)
+            );
+            assert_eq!(decode_cobs(&mut buf[..frame_len]), Ok(text));
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            decode_cobs::<u8>(&mut [3, 1, 0]),
+            Err(Error::InvalidRepresentation)
+        );
+    }
+
+    #[test]
+    fn accumulator() {
+        let mut stream = [0u8; 64];
+        let mut len = 0;
+        for text in ["one", "two", "three"] {
+            len += encode_cobs(&packet(text), &mut stream[len..]).unwrap();
+        }
+
+        let mut accumulator = CobsAccumulator::<16>::new();
+        let mut decoded = 0;
+        for byte in stream[..len].chunks(1) {
+            match accumulator.feed::<Packet>(byte) {
+                FeedResult::Consumed => (),
+                FeedResult::Success { value, remaining } => {
+                    let text = ["one", "two", "three"][decoded];
+                    assert_eq!(value, packet(text));
+                    assert!(remaining.is_empty());
+                    decoded += 1;
+                }
+                FeedResult::Error { error, .. } => panic!("{:?}", error),
+            }
+        }
+        assert_eq!(decoded, 3);
+
+        let mut remaining = &stream[..len];
+        let mut decoded = 0;
+        while !remaining.is_empty() {
+            remaining = match accumulator.feed::<Packet>(remaining) {
+                FeedResult::Success { remaining, .. } => remaining,
+                _ => panic!(),
+            };
+            decoded += 1;
+        }
+        assert_eq!(decoded, 3);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut stream = [0u8; 64];
+        let mut len = 0;
+        for text in ["a much longer text", "short"] {
+            len += encode_cobs(&packet(text), &mut stream[len..]).unwrap();
+        }
+
+        let mut accumulator = CobsAccumulator::<16>::new();
+        let remaining = match accumulator.feed::<Packet>(&stream[..len]) {
+            FeedResult::Error {
+                error: Error::LengthExceeded,
+                remaining,
+            } => remaining,
+            _ => panic!(),
+        };
+        assert_eq!(
+            accumulator.feed::<Packet>(remaining),
+            FeedResult::Success {
+                value: packet("short"),
+                remaining: &[],
+            }
+        );
+    }
+}
@@ -0,0 +1,325 @@
+use core::convert::TryFrom;
+
+use crate::de::Deserializer;
+use crate::ser::{Serializer, Writer};
+use crate::{BoundedBytes, BoundedStr, Error};
+
+/// Types that encode themselves without going through serde.
+///
+/// Implementations must produce the same bytes as `fermion::encode`, which
+/// is what `#[derive(Encode)]` generates for structs and enums.
+pub trait Encode {
+    /// Writes the encoding of `self` to `serializer`
+    fn encode<W: Writer>(
+        &self,
+        serializer: &mut Serializer<W>,
+    ) -> Result<(), Error>;
+}
+
+/// Types that decode themselves without going through serde.
+///
+/// The counterpart of `Encode`, accepting the same bytes as
+/// `fermion::decode`.
+pub trait Decode<'de>: Sized {
+    /// Reads a value from `deserializer`
+    fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error>;
+}
+
+macro_rules! impl_int {
+    ($($t:ty as $u:ty: $write:ident, $read:ident;)*) => {
+        $(
+            impl Encode for $t {
+                #[inline]
+                fn encode<W: Writer>(
+                    &self,
+                    serializer: &mut Serializer<W>,
+                ) -> Result<(), Error> {
+                    serializer.$write(*self as $u)
+                }
+            }
+
+            impl<'de> Decode<'de> for $t {
+                #[inline]
+                fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+                    Ok(deserializer.$read()? as $t)
+                }
+            }
+        )*
+    };
+}
+
+impl_int! {
+    u8 as u8: write_u8, read_u8;
+    u16 as u16: write_u16, read_u16;
+    u32 as u32: write_u32, read_u32;
+    u64 as u64: write_u64, read_u64;
+    u128 as u128: write_u128, read_u128;
+    i8 as u8: write_u8, read_u8;
+    i16 as u16: write_u16, read_u16;
+    i32 as u32: write_u32, read_u32;
+    i64 as u64: write_u64, read_u64;
+    i128 as u128: write_u128, read_u128;
+}
+
+impl Encode for usize {
+    #[inline]
+    fn encode<W: Writer>(
+        &self,
+        serializer: &mut Serializer<W>,
+    ) -> Result<(), Error> {
+        serializer.write_u64(*self as u64)
+    }
+}
+
+impl<'de> Decode<'de> for usize {
+    #[inline]
+    fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+        usize::try_from(deserializer.read_u64()?)
+            .map_err(|_| Error::InvalidRepresentation)
+    }
+}
+
+impl Encode for isize {
+    #[inline]
+    fn encode<W: Writer>(
+        &self,
+        serializer: &mut Serializer<W>,
+    ) -> Result<(), Error> {
+        serializer.write_u64(*self as u64)
+    }
+}
+
+impl<'de> Decode<'de> for isize {
+    #[inline]
+    fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+        isize::try_from(deserializer.read_u64()? as i64)
+            .map_err(|_| Error::InvalidRepresentation)
+    }
+}
+
+impl Encode for bool {
+    #[inline]
+    fn encode<W: Writer>(
+        &self,
+        serializer: &mut Serializer<W>,
+    ) -> Result<(), Error> {
+        serializer.write_u8(*self as u8)
+    }
+}
+
+impl<'de> Decode<'de> for bool {
+    #[inline]
+    fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+        match deserializer.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::InvalidRepresentation),
+        }
+    }
+}
+
+impl Encode for char {
+    #[inline]
+    fn encode<W: Writer>(
+        &self,
+        serializer: &mut Serializer<W>,
+    ) -> Result<(), Error> {
+        serializer.write_char(*self)
+    }
+}
+
+impl<'de> Decode<'de> for char {
+    #[inline]
+    fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+        deserializer.read_char()
+    }
+}
+
+impl Encode for () {
+    #[inline]
+    fn encode<W: Writer>(
+        &self,
+        _serializer: &mut Serializer<W>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'de> Decode<'de> for () {
+    #[inline]
+    fn decode(_deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+        Ok(())
+    }
+}
+
+impl Encode for str {
+    #[inline]
+    fn encode<W: Writer>(
+        &self,
+        serializer: &mut Serializer<W>,
+    ) -> Result<(), Error> {
+        serializer.write_str(self)
+    }
+}
+
+impl<'de: 'a, 'a> Decode<'de> for &'a str {
+    #[inline]
+    fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+        deserializer.read_str()
+    }
+}
+
+impl Encode for [u8] {
+    #[inline]
+    fn encode<W: Writer>(
+        &self,
+        serializer: &mut Serializer<W>,
+    ) -> Result<(), Error> {
+        serializer.write_bytes(self)
+    }
+}
+
+impl<'de: 'a, 'a> Decode<'de> for &'a [u8] {
+    #[inline]
+    fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+        deserializer.read_bytes()
+    }
+}
+
+impl<const N: usize> Encode for BoundedStr<'_, N> {
+    #[inline]
+    fn encode<W: Writer>(
+        &self,
+        serializer: &mut Serializer<W>,
+    ) -> Result<(), Error> {
+        serializer.write_bounded(self.as_bytes(), N)
+    }
+}
+
+impl<'de, const N: usize> Decode<'de> for BoundedStr<'de, N> {
+    #[inline]
+    fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+        let bytes = deserializer.read_bounded(N)?;
+        let s = core::str::from_utf8(bytes)
+            .map_err(|_| Error::InvalidRepresentation)?;
+        BoundedStr::new(s)
+    }
+}
+
+impl<const N: usize> Encode for BoundedBytes<'_, N> {
+    #[inline]
+    fn encode<W: Writer>(
+        &self,
+        serializer: &mut Serializer<W>,
+    ) -> Result<(), Error> {
+        serializer.write_bounded(self.as_bytes(), N)
+    }
+}
+
+impl<'de, const N: usize> Decode<'de> for BoundedBytes<'de, N> {
+    #[inline]
+    fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+        BoundedBytes::new(deserializer.read_bounded(N)?)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    #[inline]
+    fn encode<W: Writer>(
+        &self,
+        serializer: &mut Serializer<W>,
+    ) -> Result<(), Error> {
+        (**self).encode(serializer)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    #[inline]
+    fn encode<W: Writer>(
+        &self,
+        serializer: &mut Serializer<W>,
+    ) -> Result<(), Error> {
+        match self {
+            None => serializer.write_u8(0),
+            Some(value) => {
+                serializer.write_u8(1)?;
+                value.encode(serializer)
+            }
+        }
+    }
+}
+
+impl<'de, T: Decode<'de>> Decode<'de> for Option<T> {
+    #[inline]
+    fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+        match deserializer.read_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(deserializer)?)),
+            _ => Err(Error::InvalidRepresentation),
+        }
+    }
+}
+
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    #[inline]
+    fn encode<W: Writer>(
+        &self,
+        serializer: &mut Serializer<W>,
+    ) -> Result<(), Error> {
+        for item in self {
+            item.encode(serializer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'de, T: Decode<'de>, const N: usize> Decode<'de> for [T; N] {
+    fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+        let mut error = None;
+        let items: [Option<T>; N] = core::array::from_fn(|_| {
+            if error.is_some() {
+                return None;
+            }
+            T::decode(deserializer).map_err(|e| error = Some(e)).ok()
+        });
+        match error {
+            Some(e) => Err(e),
+            None => Ok(items.map(|item| item.unwrap())),
+        }
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($t:ident $i:tt)+) => {
+        impl<$($t: Encode),+> Encode for ($($t,)+) {
+            #[inline]
+            fn encode<W: Writer>(
+                &self,
+                serializer: &mut Serializer<W>,
+            ) -> Result<(), Error> {
+                $(self.$i.encode(serializer)?;)+
+                Ok(())
+            }
+        }
+
+        impl<'de, $($t: Decode<'de>),+> Decode<'de> for ($($t,)+) {
+            #[inline]
+            fn decode(deserializer: &mut Deserializer<'de>) -> Result<Self, Error> {
+                Ok(($($t::decode(deserializer)?,)+))
+            }
+        }
+    };
+}
+
+impl_tuple!(A 0);
+impl_tuple!(A 0 B 1);
+impl_tuple!(A 0 B 1 C 2);
+impl_tuple!(A 0 B 1 C 2 D 3);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5 G 6);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8 J 9);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8 J 9 K 10);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8 J 9 K 10 L 11);
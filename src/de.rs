@@ -2,16 +2,34 @@ use encode_unicode::Utf8Char;
 use serde::de::{DeserializeSeed, IntoDeserializer, Visitor};
 use serde::Deserialize;
 
-use crate::Error;
+use crate::bounded::SHORT_PREFIX;
+use crate::evolving::{EvolvingStruct, ReadStruct, EVOLVING};
+use crate::{too_short, Error, Limits};
 
+/// Reads the fermion encoding from a byte buffer
 pub struct Deserializer<'a> {
     buf: &'a [u8],
     ofs: usize,
+    // The segment following `buf`, and the length of those preceding it
+    next: &'a [u8],
+    base: usize,
+    // Space for borrowed values straddling two segments
+    scratch: &'a mut [u8],
+    // Set by a `SHORT_PREFIX` newtype, consumed by the next length prefix
+    short_prefix: bool,
+    canonical: bool,
+    limits: Limits,
+    depth: usize,
+    elements: usize,
 }
 
 struct SeqAccess<'a, 'b: 'a> {
     deserializer: &'a mut Deserializer<'b>,
     len: usize,
+    // The fields of an evolving struct run out here, if not before
+    end: Option<usize>,
+    // Whether the visitor was told the sequence ended
+    ended: bool,
 }
 
 type DeserializeResult<T> = Result<T, Error>;
@@ -23,6 +41,16 @@ impl<'a, 'b: 'a> serde::de::SeqAccess<'b> for SeqAccess<'a, 'b> {
         &mut self,
         seed: V,
     ) -> Result<Option<V::Value>, Error> {
+        if let Some(end) = self.end {
+            if self.len > 0 && self.deserializer.position() >= end {
+                // fields added after the input was encoded
+                if self.deserializer.canonical {
+                    return Err(Error::InvalidRepresentation);
+                }
+                self.ended = true;
+                return Ok(None);
+            }
+        }
         if self.len > 0 {
             self.len -= 1;
             Ok(Some(DeserializeSeed::deserialize(
@@ -30,6 +58,7 @@ impl<'a, 'b: 'a> serde::de::SeqAccess<'b> for SeqAccess<'a, 'b> {
                 &mut *self.deserializer,
             )?))
         } else {
+            self.ended = true;
             Ok(None)
         }
     }
@@ -40,95 +69,405 @@ impl<'a, 'b: 'a> serde::de::SeqAccess<'b> for SeqAccess<'a, 'b> {
 }
 
 impl<'de> Deserializer<'de> {
+    /// Creates a deserializer reading from the start of `buf`
     pub fn new(buf: &'de [u8]) -> Self {
-        Deserializer { buf, ofs: 0 }
+        Self::with_limits(buf, Limits::default())
+    }
+
+    /// Creates a deserializer that enforces `limits` while reading `buf`
+    pub fn with_limits(buf: &'de [u8], limits: Limits) -> Self {
+        Deserializer {
+            buf,
+            ofs: 0,
+            next: &[],
+            base: 0,
+            scratch: &mut [],
+            short_prefix: false,
+            canonical: false,
+            limits,
+            depth: 0,
+            elements: 0,
+        }
+    }
+
+    /// Creates a deserializer reading `first` and then `second`, such as
+    /// the two halves of the contents of a ring buffer
+    ///
+    /// Integers and `char`s are read across the boundary, while a borrowed
+    /// `str` or byte slice straddling it fails with `Error::SegmentBoundary`
+    /// unless scratch space is provided with `with_scratch`.
+    pub fn from_segments(first: &'de [u8], second: &'de [u8]) -> Self {
+        let mut deserializer = Self::new(first);
+        deserializer.next = second;
+        deserializer
+    }
+
+    /// Provides space to copy borrowed values straddling two segments to
+    pub fn with_scratch(mut self, scratch: &'de mut [u8]) -> Self {
+        self.scratch = scratch;
+        self
+    }
+
+    /// Rejects input that doesn't encode exactly as read, such as structs
+    /// missing or skipping fields
+    pub(crate) fn canonical(mut self) -> Self {
+        self.canonical = true;
+        self
     }
 
-    #[inline(always)]
     fn assert_space(&self, space: usize) -> Result<(), Error> {
-        if self.ofs + space > self.buf.len() {
-            Err(Error::OutOfSpace)
+        if self.ofs + space > self.limits.max_bytes {
+            Err(Error::LimitExceeded)
+        } else if self.ofs + space > self.buf.len() + self.next.len() {
+            Err(Error::OutOfSpace {
+                needed: self.base + self.ofs + space,
+                available: self.base + self.buf.len() + self.next.len(),
+            })
         } else {
             Ok(())
         }
     }
 
+    /// Number of bytes consumed so far
+    pub(crate) fn position(&self) -> usize {
+        self.base + self.ofs
+    }
+
+    /// Fails unless the whole input has been consumed
+    pub(crate) fn end(&self) -> Result<(), Error> {
+        if self.ofs == self.buf.len() && self.next.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::TrailingBytes)
+        }
+    }
+
     #[inline]
-    fn read_u8(&mut self) -> Result<u8, Error> {
-        self.assert_space(1)?;
-        let val = unsafe { *self.buf.get_unchecked(self.ofs) };
-        self.ofs += 1;
-        Ok(val)
+    fn enter(&mut self) -> Result<(), Error> {
+        if self.depth >= self.limits.max_depth {
+            return Err(Error::LimitExceeded);
+        }
+        self.depth += 1;
+        Ok(())
     }
 
     #[inline]
-    fn read_u16(&mut self) -> Result<u16, Error> {
-        self.assert_space(2)?;
-        let mut val;
-        unsafe {
-            val = *self.buf.get_unchecked(self.ofs) as u16;
-            val |= (*self.buf.get_unchecked(self.ofs + 1) as u16) << 8;
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Visits `len` elements, or those before `end` of an evolving struct
+    fn visit_seq<V: Visitor<'de>>(
+        &mut self,
+        visitor: V,
+        len: usize,
+        end: Option<usize>,
+    ) -> Result<V::Value, Error> {
+        self.enter()?;
+        let mut seq = SeqAccess {
+            deserializer: self,
+            len,
+            end,
+            ended: false,
+        };
+        let value = visitor.visit_seq(&mut seq);
+        let ended = seq.ended;
+        self.leave();
+        too_short(value, ended)
+    }
+
+    #[inline]
+    fn count_elements(&mut self, len: usize) -> Result<(), Error> {
+        self.elements = self.elements.saturating_add(len);
+        if self.elements > self.limits.max_elements {
+            Err(Error::LimitExceeded)
+        } else {
+            Ok(())
         }
-        self.ofs += 2;
-        Ok(val)
+    }
+
+    /// Reads a single byte
+    #[inline]
+    pub fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    /// Reads a little-endian `u16`
+    #[inline]
+    pub fn read_u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    /// Reads a little-endian `u32`
+    #[inline]
+    pub fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    /// Reads a little-endian `u64`
+    #[inline]
+    pub fn read_u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    /// Reads a little-endian `u128`
+    #[inline]
+    pub fn read_u128(&mut self) -> Result<u128, Error> {
+        Ok(u128::from_le_bytes(self.read_array()?))
     }
 
     #[inline]
-    fn read_u32(&mut self) -> Result<u32, Error> {
-        self.assert_space(4)?;
-        let mut val;
-        unsafe {
-            val = *self.buf.get_unchecked(self.ofs) as u32;
-            val |= (*self.buf.get_unchecked(self.ofs + 1) as u32) << 8;
-            val |= (*self.buf.get_unchecked(self.ofs + 2) as u32) << 16;
-            val |= (*self.buf.get_unchecked(self.ofs + 3) as u32) << 24;
+    fn read_len(&mut self) -> Result<usize, Error> {
+        if core::mem::replace(&mut self.short_prefix, false) {
+            Ok(self.read_u8()? as usize)
+        } else {
+            Ok(self.read_u16()? as usize)
         }
-        self.ofs += 4;
-        Ok(val)
     }
 
+    #[inline(always)]
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut bytes = [0u8; N];
+        self.read_into(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Copies the next `out.len()` bytes into `out`
+    #[inline(always)]
+    fn read_into(&mut self, out: &mut [u8]) -> Result<(), Error> {
+        let end = self.ofs + out.len();
+        if end <= self.buf.len() && end <= self.limits.max_bytes {
+            out.copy_from_slice(unsafe {
+                self.buf.get_unchecked(self.ofs..end)
+            });
+            self.ofs = end;
+            Ok(())
+        } else {
+            self.read_into_seam(out)
+        }
+    }
+
+    #[cold]
+    fn read_into_seam(&mut self, out: &mut [u8]) -> Result<(), Error> {
+        self.assert_space(out.len())?;
+        let (first, rest) = out.split_at_mut(self.buf.len() - self.ofs);
+        first.copy_from_slice(&self.buf[self.ofs..]);
+        self.next_segment();
+        self.read_into(rest)
+    }
+
+    #[inline(always)]
+    fn read_slice(&mut self, len: usize) -> Result<&'de [u8], Error> {
+        let end = self.ofs + len;
+        if end <= self.buf.len() && end <= self.limits.max_bytes {
+            let slice = unsafe { self.buf.get_unchecked(self.ofs..end) };
+            self.ofs = end;
+            Ok(slice)
+        } else {
+            self.read_slice_seam(len)
+        }
+    }
+
+    /// Reads a slice that doesn't lie in the current segment, copying it to
+    /// the scratch space if it straddles the next one
+    #[cold]
+    fn read_slice_seam(&mut self, len: usize) -> Result<&'de [u8], Error> {
+        self.assert_space(len)?;
+        if self.ofs == self.buf.len() {
+            self.next_segment();
+            return self.read_slice(len);
+        }
+        if len > self.scratch.len() {
+            return Err(Error::SegmentBoundary);
+        }
+        let (copy, rest) = core::mem::take(&mut self.scratch).split_at_mut(len);
+        self.scratch = rest;
+        self.read_into(copy)?;
+        Ok(copy)
+    }
+
+    /// Skips the next `len` bytes
+    fn skip(&mut self, mut len: usize) -> Result<(), Error> {
+        self.assert_space(len)?;
+        while self.ofs + len > self.buf.len() {
+            len -= self.buf.len() - self.ofs;
+            self.next_segment();
+        }
+        self.ofs += len;
+        Ok(())
+    }
+
+    /// Reads a value after its size, skipping what it leaves unread
+    fn read_evolving<V: Visitor<'de>>(
+        &mut self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let len = self.read_len()?;
+        let end = self.position() + len;
+        self.enter()?;
+        let value = visitor.visit_newtype_struct(EvolvingStruct {
+            deserializer: &mut *self,
+            end,
+        });
+        self.leave();
+        let value = value?;
+        match end.checked_sub(self.position()) {
+            Some(0) => Ok(value),
+            Some(_) if self.canonical => Err(Error::TrailingBytes),
+            Some(unknown) => self.skip(unknown).map(|()| value),
+            None => Err(Error::InvalidRepresentation),
+        }
+    }
+
+    #[inline]
+    fn next_segment(&mut self) {
+        self.base += self.buf.len();
+        self.limits.max_bytes =
+            self.limits.max_bytes.saturating_sub(self.buf.len());
+        self.buf = core::mem::take(&mut self.next);
+        self.ofs = 0;
+    }
+
+    /// Reads a length-prefixed byte slice, borrowed from the input
     #[inline]
-    fn read_u64(&mut self) -> Result<u64, Error> {
-        self.assert_space(8)?;
-        let mut val;
-        unsafe {
-            val = *self.buf.get_unchecked(self.ofs) as u64;
-            val |= (*self.buf.get_unchecked(self.ofs + 1) as u64) << 8;
-            val |= (*self.buf.get_unchecked(self.ofs + 2) as u64) << 16;
-            val |= (*self.buf.get_unchecked(self.ofs + 3) as u64) << 24;
-            val |= (*self.buf.get_unchecked(self.ofs + 4) as u64) << 32;
-            val |= (*self.buf.get_unchecked(self.ofs + 5) as u64) << 40;
-            val |= (*self.buf.get_unchecked(self.ofs + 6) as u64) << 48;
-            val |= (*self.buf.get_unchecked(self.ofs + 7) as u64) << 56;
+    pub fn read_bytes(&mut self) -> Result<&'de [u8], Error> {
+        let len = self.read_len()?;
+        self.read_slice(len)
+    }
+
+    /// Reads a byte slice of at most `bound` bytes, as `BoundedBytes` does
+    #[inline]
+    pub(crate) fn read_bounded(
+        &mut self,
+        bound: usize,
+    ) -> Result<&'de [u8], Error> {
+        self.short_prefix = bound <= u8::MAX as usize;
+        let bytes = self.read_bytes()?;
+        if bytes.len() > bound {
+            Err(Error::LengthExceeded)
+        } else {
+            Ok(bytes)
         }
-        self.ofs += 8;
-        Ok(val)
-    }
-
-    fn read_u128(&mut self) -> Result<u128, Error> {
-        self.assert_space(16)?;
-        let mut val;
-        unsafe {
-            val = *self.buf.get_unchecked(self.ofs) as u128;
-            val |= (*self.buf.get_unchecked(self.ofs + 1) as u128) << 8;
-            val |= (*self.buf.get_unchecked(self.ofs + 2) as u128) << 16;
-            val |= (*self.buf.get_unchecked(self.ofs + 3) as u128) << 24;
-            val |= (*self.buf.get_unchecked(self.ofs + 4) as u128) << 32;
-            val |= (*self.buf.get_unchecked(self.ofs + 5) as u128) << 40;
-            val |= (*self.buf.get_unchecked(self.ofs + 6) as u128) << 48;
-            val |= (*self.buf.get_unchecked(self.ofs + 7) as u128) << 56;
-
-            val |= (*self.buf.get_unchecked(self.ofs + 8) as u128) << 64;
-            val |= (*self.buf.get_unchecked(self.ofs + 9) as u128) << 72;
-            val |= (*self.buf.get_unchecked(self.ofs + 10) as u128) << 80;
-            val |= (*self.buf.get_unchecked(self.ofs + 11) as u128) << 88;
-            val |= (*self.buf.get_unchecked(self.ofs + 12) as u128) << 96;
-            val |= (*self.buf.get_unchecked(self.ofs + 13) as u128) << 104;
-            val |= (*self.buf.get_unchecked(self.ofs + 14) as u128) << 112;
-            val |= (*self.buf.get_unchecked(self.ofs + 15) as u128) << 120;
+    }
+
+    /// Reads a length-prefixed `str`, borrowed from the input
+    #[inline]
+    pub fn read_str(&mut self) -> Result<&'de str, Error> {
+        core::str::from_utf8(self.read_bytes()?)
+            .map_err(|_| Error::InvalidRepresentation)
+    }
+
+    /// Reads a UTF-8 encoded `char`
+    pub fn read_char(&mut self) -> Result<char, Error> {
+        let mut bytes = [0u8; 4];
+        bytes[0] = self.read_u8()?;
+        let len = match bytes[0].leading_ones() {
+            0 => 1,
+            len @ 2..=4 => len as usize,
+            _ => return Err(Error::InvalidRepresentation),
+        };
+        self.read_into(&mut bytes[1..len])?;
+        match Utf8Char::from_slice_start(&bytes[..len]) {
+            Ok((c, count)) if count == len => Ok(c.to_char()),
+            _ => Err(Error::InvalidRepresentation),
         }
-        self.ofs += 16;
-        Ok(val)
+    }
+}
+
+/// Reads bits packed least significant bit first, for the packed encoding
+pub(crate) struct BitReader<'a> {
+    buf: &'a [u8],
+    // Position in bits
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        BitReader { buf, pos: 0 }
+    }
+
+    /// Number of bytes consumed so far, counting a partial one
+    pub(crate) fn position(&self) -> usize {
+        self.pos.div_ceil(8)
+    }
+
+    /// Number of bits consumed so far
+    pub(crate) fn bit_position(&self) -> usize {
+        self.pos
+    }
+
+    /// Skips to the bit at `pos`, failing if it lies past the input
+    pub(crate) fn skip_to(&mut self, pos: usize) -> Result<(), Error> {
+        if pos > self.buf.len() * 8 {
+            return Err(Error::OutOfSpace {
+                needed: pos.div_ceil(8),
+                available: self.buf.len(),
+            });
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    /// Number of bits left to read
+    pub(crate) fn remaining(&self) -> usize {
+        self.buf.len() * 8 - self.pos
+    }
+
+    /// Reads `width` bits, at most 64
+    pub(crate) fn read_bits(&mut self, width: u32) -> Result<u64, Error> {
+        let width = width as usize;
+        if self.pos + width > self.buf.len() * 8 {
+            return Err(Error::OutOfSpace {
+                needed: (self.pos + width).div_ceil(8),
+                available: self.buf.len(),
+            });
+        }
+        let mut value = 0;
+        let mut read = 0;
+        while read < width {
+            let byte = (self.buf[self.pos / 8] >> (self.pos % 8)) as u64;
+            let take = (8 - self.pos % 8).min(width - read);
+            value |= (byte & ((1 << take) - 1)) << read;
+            read += take;
+            self.pos += take;
+        }
+        Ok(value)
+    }
+
+    /// Skips to the next byte boundary, failing unless the padding bits are
+    /// zero
+    pub(crate) fn align(&mut self) -> Result<(), Error> {
+        let padding = (8 - self.pos % 8) % 8;
+        if self.read_bits(padding as u32)? != 0 {
+            return Err(Error::InvalidRepresentation);
+        }
+        Ok(())
+    }
+
+    /// Reads `len` whole bytes, starting at the next byte boundary
+    pub(crate) fn read_slice(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        self.align()?;
+        let start = self.pos / 8;
+        let end = start + len;
+        if end > self.buf.len() {
+            return Err(Error::OutOfSpace {
+                needed: end,
+                available: self.buf.len(),
+            });
+        }
+        self.pos = end * 8;
+        Ok(&self.buf[start..end])
+    }
+}
+
+impl<'de> ReadStruct<'de> for Deserializer<'de> {
+    fn read_struct<V: Visitor<'de>>(
+        &mut self,
+        fields: &'static [&'static str],
+        visitor: V,
+        end: usize,
+    ) -> Result<V::Value, Error> {
+        self.visit_seq(visitor, fields.len(), Some(end))
     }
 }
 
@@ -242,56 +581,38 @@ impl<'de> serde::de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: serde::de::Visitor<'de>,
     {
-        match Utf8Char::from_slice_start(&self.buf[self.ofs..]) {
-            Ok((c, count)) => {
-                self.ofs += count;
-                visitor.visit_char(c.to_char())
-            }
-            Err(_) => Err(Error::InvalidRepresentation),
-        }
+        visitor.visit_char(self.read_char()?)
     }
 
     fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        let len = self.read_u16()? as usize;
-        let start = self.ofs;
-        let end = self.ofs + len;
-        self.ofs += len;
-        match core::str::from_utf8(&self.buf[start..end]) {
-            Ok(string) => Ok(visitor.visit_borrowed_str(string)?),
-            Err(_) => Err(Error::InvalidRepresentation),
-        }
+        visitor.visit_borrowed_str(self.read_str()?)
     }
 
-    fn deserialize_string<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        Err(Error::NotSupported)
+        self.deserialize_str(visitor)
     }
 
     fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        let len = self.read_u16()? as usize;
-        let start = self.ofs;
-        let end = self.ofs + len;
-        self.ofs += len;
-        // slice checks bounds by itself.
-        visitor.visit_borrowed_bytes(&self.buf[start..end])
+        visitor.visit_borrowed_bytes(self.read_bytes()?)
     }
 
     fn deserialize_byte_buf<V>(
         self,
-        _visitor: V,
+        visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        Err(Error::NotSupported)
+        self.deserialize_bytes(visitor)
     }
 
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -301,7 +622,12 @@ impl<'de> serde::de::Deserializer<'de> for &mut Deserializer<'de> {
         let value: u8 = Deserialize::deserialize(&mut *self)?;
         match value {
             0 => visitor.visit_none(),
-            1 => visitor.visit_some(self),
+            1 => {
+                self.enter()?;
+                let value = visitor.visit_some(&mut *self);
+                self.leave();
+                value
+            }
             _ => Err(Error::InvalidRepresentation),
         }
     }
@@ -326,24 +652,30 @@ impl<'de> serde::de::Deserializer<'de> for &mut Deserializer<'de> {
 
     fn deserialize_newtype_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        visitor.visit_newtype_struct(self)
+        if name == SHORT_PREFIX {
+            self.short_prefix = true;
+        } else if name == EVOLVING {
+            return self.read_evolving(visitor);
+        }
+        self.enter()?;
+        let value = visitor.visit_newtype_struct(&mut *self);
+        self.leave();
+        value
     }
 
     fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        let len = Deserialize::deserialize(&mut *self)?;
-        visitor.visit_seq(SeqAccess {
-            deserializer: self,
-            len: len,
-        })
+        let len = self.read_len()?;
+        self.count_elements(len)?;
+        self.visit_seq(visitor, len, None)
     }
 
     fn deserialize_tuple<V>(
@@ -354,10 +686,7 @@ impl<'de> serde::de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: serde::de::Visitor<'de>,
     {
-        visitor.visit_seq(SeqAccess {
-            deserializer: self,
-            len: len,
-        })
+        self.visit_seq(visitor, len, None)
     }
 
     fn deserialize_tuple_struct<V>(
@@ -388,7 +717,7 @@ impl<'de> serde::de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: serde::de::Visitor<'de>,
     {
-        self.deserialize_tuple(fields.len(), visitor)
+        self.visit_seq(visitor, fields.len(), None)
     }
 
     fn deserialize_enum<V>(
@@ -400,7 +729,10 @@ impl<'de> serde::de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: serde::de::Visitor<'de>,
     {
-        visitor.visit_enum(self)
+        self.enter()?;
+        let value = visitor.visit_enum(&mut *self);
+        self.leave();
+        value
     }
 
     fn deserialize_identifier<V>(
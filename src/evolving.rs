@@ -0,0 +1,374 @@
+//! Structs that can gain fields without breaking deployed decoders.
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+
+use serde::de::{Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::max_size::MaxSize;
+use crate::Error;
+
+/// Newtype name that makes fermion write the encoded size of the struct it
+/// holds before it. Other formats just see a newtype struct.
+pub(crate) const EVOLVING: &str = "$fermion::Evolving";
+
+/// A struct encoded after its size in bytes, so that fields can be added
+/// to its end in later versions.
+///
+/// Decoders of an older version skip the fields they don't know. Decoders
+/// of a newer version give fields missing from the input their default
+/// value, which makes `#[serde(default)]` required on added fields;
+/// without it they fail with `Error::Custom`. `decode_canonical` accepts
+/// neither, as the value would encode differently, while the framed, COBS
+/// and `tokio` codec decoders do.
+///
+/// Only structs with named fields can evolve, so decoding other values
+/// fails with `Error::NotSupported` in fermion.
+///
+/// The size takes 2 bytes, and counts as a length prefix towards the
+/// limit of 65535 bytes. The packed encoding counts it in bits instead, up
+/// to 65535, and starts the struct at the next byte boundary.
+///
+/// ```
+/// use fermion::Evolving;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// struct ReadingV1 {
+///     celsius: i16,
+/// }
+///
+/// #[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// struct ReadingV2 {
+///     celsius: i16,
+///     #[serde(default)]
+///     humidity: Option<u8>,
+/// }
+///
+/// let mut buf = [0u8; 8];
+/// let len = fermion::encode(&Evolving(ReadingV1 { celsius: 21 }), &mut buf);
+/// assert_eq!(
+///     fermion::decode(&buf[..len.unwrap()]),
+///     Ok(Evolving(ReadingV2 { celsius: 21, humidity: None }))
+/// );
+///
+/// let v2 = ReadingV2 { celsius: 21, humidity: Some(40) };
+/// let len = fermion::encode(&Evolving(v2), &mut buf);
+/// assert_eq!(
+///     fermion::decode(&buf[..len.unwrap()]),
+///     Ok(Evolving(ReadingV1 { celsius: 21 }))
+/// );
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct Evolving<T>(pub T);
+
+impl<T> Deref for Evolving<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Evolving<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: MaxSize> MaxSize for Evolving<T> {
+    const MAX_SIZE: usize = 2 + T::MAX_SIZE;
+}
+
+impl<T: Serialize> Serialize for Evolving<T> {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(EVOLVING, &self.0)
+    }
+}
+
+struct EvolvingVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for EvolvingVisitor<T> {
+    type Value = Evolving<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a struct after its size")
+    }
+
+    fn visit_newtype_struct<D>(
+        self,
+        deserializer: D,
+    ) -> Result<Evolving<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Evolving)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Evolving<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer
+            .deserialize_newtype_struct(EVOLVING, EvolvingVisitor(PhantomData))
+    }
+}
+
+/// Deserializers that can end a struct early, at the size of an `Evolving`
+pub(crate) trait ReadStruct<'de> {
+    /// Reads the fields of a struct, those past `end` taking their default
+    fn read_struct<V: Visitor<'de>>(
+        &mut self,
+        fields: &'static [&'static str],
+        visitor: V,
+        end: usize,
+    ) -> Result<V::Value, Error>;
+}
+
+/// Hands the end of an `Evolving` to the struct it holds, failing for any
+/// other value
+pub(crate) struct EvolvingStruct<'a, D> {
+    pub(crate) deserializer: &'a mut D,
+    pub(crate) end: usize,
+}
+
+impl<'de, D: ReadStruct<'de>> Deserializer<'de> for EvolvingStruct<'_, D> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserializer.read_struct(fields, visitor, self.end)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        decode, decode_canonical, decode_cobs, decode_framed, decode_segmented,
+        encode, encode_cobs, encode_fragmented, encode_framed, encoded_size,
+        Crc16, Reassembler,
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct ConfigV1<'a> {
+        interval: u16,
+        name: &'a str,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct ConfigV2<'a> {
+        interval: u16,
+        name: &'a str,
+        #[serde(default)]
+        retries: u8,
+        #[serde(default)]
+        backoff: Option<u32>,
+    }
+
+    /// A new version adding a field without a default
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Required<'a> {
+        interval: u16,
+        name: &'a str,
+        retries: u8,
+    }
+
+    /// A message carrying a config, with a field after it
+    type Message<T> = (u8, Evolving<T>, u32);
+
+    /// A struct evolving on its own, holding a config
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Node<T> {
+        config: Evolving<T>,
+        id: u8,
+    }
+
+    const V1: ConfigV1 = ConfigV1 {
+        interval: 60,
+        name: "node",
+    };
+
+    const V2: ConfigV2 = ConfigV2 {
+        interval: 60,
+        name: "node",
+        retries: 3,
+        backoff: Some(500),
+    };
+
+    #[test]
+    fn old_to_new() {
+        let mut buf = [0u8; 32];
+        let len = encode(&(7u8, Evolving(V1), 9u32), &mut buf).unwrap();
+        assert_eq!(&buf[1..3], &[8, 0]);
+        assert_eq!(len, 1 + 2 + 8 + 4);
+
+        let v2 = ConfigV2 {
+            retries: 0,
+            backoff: None,
+            ..V2
+        };
+        assert_eq!(decode(&buf[..len]), Ok((7u8, Evolving(v2), 9u32)));
+        assert_eq!(
+            decode::<Message<Required>>(&buf[..len]),
+            Err(Error::Custom)
+        );
+        assert_eq!(
+            decode_canonical::<Message<ConfigV2>>(&buf[..len]),
+            Err(Error::InvalidRepresentation)
+        );
+    }
+
+    #[test]
+    fn new_to_old() {
+        let mut buf = [0u8; 32];
+        let len = encode(&(7u8, Evolving(V2), 9u32), &mut buf).unwrap();
+        assert_eq!(&buf[1..3], &[8 + 1 + 5, 0]);
+
+        assert_eq!(decode(&buf[..len]), Ok((7u8, Evolving(V1), 9u32)));
+        assert_eq!(
+            decode_canonical::<Message<ConfigV1>>(&buf[..len]),
+            Err(Error::TrailingBytes)
+        );
+
+        // skipping across the segments of a ring buffer
+        let (first, second) = buf[..len].split_at(12);
+        assert_eq!(
+            decode_segmented(first, second, &mut []),
+            Ok((7u8, Evolving(V1), 9u32))
+        );
+    }
+
+    #[test]
+    fn transports() {
+        let v2 = ConfigV2 {
+            retries: 0,
+            backoff: None,
+            ..V2
+        };
+
+        let mut buf = [0u8; 32];
+        let len = encode_framed::<Crc16, _>(&Evolving(V1), &mut buf).unwrap();
+        assert_eq!(decode_framed::<Crc16, _>(&buf[..len]), Ok(Evolving(v2)));
+        let len = encode_framed::<Crc16, _>(&Evolving(V2), &mut buf).unwrap();
+        assert_eq!(decode_framed::<Crc16, _>(&buf[..len]), Ok(Evolving(V1)));
+
+        let len = encode_cobs(&Evolving(V2), &mut buf).unwrap();
+        assert_eq!(decode_cobs(&mut buf[..len]), Ok(Evolving(V1)));
+
+        // the value must still fill the frame
+        let len = encode_framed::<Crc16, _>(&(Evolving(V1), 0u8), &mut buf);
+        assert_eq!(
+            decode_framed::<Crc16, Evolving<ConfigV1>>(&buf[..len.unwrap()]),
+            Err(Error::TrailingBytes)
+        );
+    }
+
+    #[test]
+    fn same_version() {
+        let mut buf = [0u8; 32];
+        let len = encode(&Evolving(V2), &mut buf).unwrap();
+        assert_eq!(len, encoded_size(&V2).unwrap() + 2);
+        assert_eq!(decode_canonical(&buf[..len]), Ok(Evolving(V2)));
+
+        // nested, each skipping its own new fields
+        let nested = (
+            Evolving(Node {
+                config: Evolving(V2),
+                id: 1,
+            }),
+            2u8,
+        );
+        let len = encode(&nested, &mut buf).unwrap();
+        assert_eq!(
+            decode(&buf[..len]),
+            Ok((
+                Evolving(Node {
+                    config: Evolving(V1),
+                    id: 1
+                }),
+                2u8
+            ))
+        );
+    }
+
+    #[test]
+    fn unpatchable() {
+        // sized in an extra pass, as fragments are emitted as they fill
+        let nested = Evolving(Node {
+            config: Evolving(V2),
+            id: 1,
+        });
+        let mut buf = [0u8; 32];
+        let len = encode(&nested, &mut buf).unwrap();
+
+        let mut reassembler = Reassembler::<32>::new();
+        let mut decoded = false;
+        encode_fragmented(&nested, &mut [0u8; 8], |fragment| {
+            if let Some(encoded) = reassembler.push(fragment)? {
+                assert_eq!(encoded, &buf[..len]);
+                decoded = true;
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert!(decoded);
+    }
+
+    #[test]
+    fn not_a_struct() {
+        let mut buf = [0u8; 32];
+        let len = encode(&Evolving((V1, 3u8)), &mut buf).unwrap();
+        assert_eq!(
+            decode::<Evolving<(ConfigV2, u8)>>(&buf[..len]),
+            Err(Error::NotSupported)
+        );
+        assert_eq!(
+            decode::<Evolving<(ConfigV1, u8)>>(&buf[..len]),
+            Err(Error::NotSupported)
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        // a size shorter than the fields, and longer than the input
+        let short = [4, 0, 60, 0, 4, 0, b'n', b'o', b'd', b'e'];
+        assert_eq!(
+            decode::<Evolving<ConfigV1>>(&short),
+            Err(Error::InvalidRepresentation)
+        );
+        let long = [9, 0, 60, 0, 1, 0, b'n'];
+        assert_eq!(
+            decode::<Evolving<ConfigV1>>(&long),
+            Err(Error::OutOfSpace {
+                needed: 11,
+                available: 7
+            })
+        );
+    }
+}
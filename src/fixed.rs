@@ -0,0 +1,167 @@
+//! Encoding into fixed-capacity `heapless` collections.
+//!
+//! Decoding needs no support of its own: `heapless::Vec` and
+//! `heapless::String` decode through serde, and a value longer than their
+//! capacity fails with `Error::LengthExceeded`.
+
+use ::heapless::Vec;
+use serde::Serialize;
+
+use crate::ser::{Serializer, Writer};
+use crate::{encoded_size, Error};
+
+/// Appends to the vector, failing with `Error::OutOfSpace` once full
+impl<const N: usize> Writer for Vec<u8, N> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.extend_from_slice(bytes)
+            .map_err(|()| Error::OutOfSpace {
+                needed: self.len() + bytes.len(),
+                available: N,
+            })
+    }
+
+    fn reserve(&mut self, len: usize) -> Result<usize, Error> {
+        let at = self.len();
+        self.resize(at + len, 0).map_err(|()| Error::OutOfSpace {
+            needed: at + len,
+            available: N,
+        })?;
+        Ok(at)
+    }
+
+    fn patch(&mut self, at: usize, bytes: &[u8]) -> Result<(), Error> {
+        self[at..at + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+/// Encodes a value into a new `heapless::Vec` of capacity `N`
+///
+/// On `Error::OutOfSpace`, `needed` is the full size of the encoded value.
+///
+/// ```
+/// let encoded = fermion::encode_heapless::<_, 8>(&(1u16, "ab")).unwrap();
+/// assert_eq!(&encoded[..], &[1, 0, 2, 0, b'a', b'b']);
+/// ```
+pub fn encode_heapless<T, const N: usize>(
+    value: &T,
+) -> Result<Vec<u8, N>, Error>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_writer(Vec::new());
+    match value.serialize(&mut serializer) {
+        Ok(()) => Ok(serializer.into_writer()),
+        Err(Error::OutOfSpace { available, .. }) => Err(Error::OutOfSpace {
+            needed: encoded_size(value)?,
+            available,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode;
+    use crate::pathological::{Evens, DIGITS};
+
+    use ::heapless::String;
+    use core::convert::TryFrom;
+    use serde::{Deserialize, Deserializer};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String<8>,
+        ports: Vec<u16, 4>,
+    }
+
+    fn config() -> Config {
+        Config {
+            name: String::try_from("sensor").unwrap(),
+            ports: Vec::from_slice(&[80, 443]).unwrap(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let encoded = encode_heapless::<_, 32>(&config()).unwrap();
+        assert_eq!(encoded.len(), 2 + 6 + 2 + 4);
+        assert_eq!(decode(&encoded), Ok(config()));
+    }
+
+    #[test]
+    fn capacity_exceeded() {
+        let long = ("sensor-01", &[1u16, 2][..]);
+        let encoded = encode_heapless::<_, 32>(&long).unwrap();
+        assert_eq!(decode::<Config>(&encoded), Err(Error::LengthExceeded));
+
+        let many = ("s", &[1u16, 2, 3, 4, 5][..]);
+        let encoded = encode_heapless::<_, 32>(&many).unwrap();
+        assert_eq!(decode::<Config>(&encoded), Err(Error::LengthExceeded));
+
+        assert_eq!(
+            encode_heapless::<_, 8>(&config()),
+            Err(Error::OutOfSpace {
+                needed: 14,
+                available: 8
+            })
+        );
+    }
+
+    #[test]
+    fn owned_string() {
+        // a string decoded through `deserialize_string`
+        #[derive(Debug, PartialEq)]
+        struct Owned(String<4>);
+
+        impl<'de> Deserialize<'de> for Owned {
+            fn deserialize<D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<Self, D::Error> {
+                struct Visitor;
+
+                impl serde::de::Visitor<'_> for Visitor {
+                    type Value = Owned;
+
+                    fn expecting(
+                        &self,
+                        f: &mut core::fmt::Formatter,
+                    ) -> core::fmt::Result {
+                        f.write_str("a short string")
+                    }
+
+                    fn visit_str<E: serde::de::Error>(
+                        self,
+                        v: &str,
+                    ) -> Result<Owned, E> {
+                        String::try_from(v)
+                            .map(Owned)
+                            .map_err(|()| E::invalid_length(v.len(), &self))
+                    }
+                }
+
+                deserializer.deserialize_string(Visitor)
+            }
+        }
+
+        assert_eq!(
+            decode::<Owned>(&[3, 0, b'a', b'b', b'c']),
+            Ok(Owned(String::try_from("abc").unwrap()))
+        );
+        assert_eq!(
+            decode::<Owned>(&[5, 0, b'a', b'b', b'c', b'd', b'e']),
+            Err(Error::LengthExceeded)
+        );
+    }
+
+    #[test]
+    fn unknown_length() {
+        let encoded = encode_heapless::<_, 8>(&Evens(&DIGITS)).unwrap();
+        assert_eq!(&encoded[..], &[5, 0, 0, 2, 4, 6, 8]);
+    }
+}
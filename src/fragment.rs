@@ -0,0 +1,330 @@
+//! Splitting encoded values across fragments, for links with a small MTU.
+//!
+//! Each fragment starts with a header byte holding its index, with the top
+//! bit set on the last fragment of a value, so a value spans at most 128
+//! fragments.
+
+use serde::Serialize;
+
+use crate::ser::{Serializer, Writer};
+use crate::Error;
+
+const LAST: u8 = 0x80;
+const MAX_FRAGMENTS: usize = 128;
+
+struct Fragmenter<'a, F> {
+    buf: &'a mut [u8],
+    len: usize,
+    index: usize,
+    // Bytes of the value in the fragments already emitted
+    emitted: usize,
+    emit: F,
+}
+
+impl<F> Fragmenter<'_, F>
+where
+    F: FnMut(&[u8]) -> Result<(), Error>,
+{
+    fn flush(&mut self, last: bool) -> Result<(), Error> {
+        if self.index >= MAX_FRAGMENTS {
+            return Err(Error::LengthExceeded);
+        }
+        self.buf[0] = self.index as u8 | if last { LAST } else { 0 };
+        (self.emit)(&self.buf[..self.len])?;
+        self.index += 1;
+        self.emitted += self.len - 1;
+        self.len = 1;
+        Ok(())
+    }
+}
+
+impl<F> Writer for Fragmenter<'_, F>
+where
+    F: FnMut(&[u8]) -> Result<(), Error>,
+{
+    fn write(&mut self, mut bytes: &[u8]) -> Result<(), Error> {
+        while !bytes.is_empty() {
+            // a full fragment is only sent once more follows, as the last
+            // one is flagged
+            if self.len == self.buf.len() {
+                self.flush(false)?;
+            }
+            let count = bytes.len().min(self.buf.len() - self.len);
+            self.buf[self.len..self.len + count]
+                .copy_from_slice(&bytes[..count]);
+            self.len += count;
+            bytes = &bytes[count..];
+        }
+        Ok(())
+    }
+
+    fn reserve(&mut self, len: usize) -> Result<usize, Error> {
+        let at = self.emitted + self.len - 1;
+        for _ in 0..len {
+            self.write(&[0])?;
+        }
+        Ok(at)
+    }
+
+    fn patch(&mut self, at: usize, bytes: &[u8]) -> Result<(), Error> {
+        // bytes already emitted can't be taken back
+        if at < self.emitted {
+            return Err(Error::UnknownLength);
+        }
+        let start = 1 + at - self.emitted;
+        self.buf[start..start + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Encodes a value as a series of fragments the size of `fragment`
+///
+/// `fragment` is the scratch space each fragment is assembled in, and is
+/// passed to `emit` once filled, or trimmed to the end of the value for the
+/// last one. Returns the number of fragments emitted.
+///
+/// The length of a sequence of unknown length, or of `collect_str`, is
+/// patched in at its end, which fails with `Error::UnknownLength` once the
+/// fragment holding the length has been emitted.
+///
+/// ```
+/// let text = core::str::from_utf8(&[b'x'; 100]).unwrap();
+///
+/// let mut count = 0;
+/// let fragments = fermion::encode_fragmented(
+///     &text,
+///     &mut [0u8; 64],
+///     |fragment| {
+///         count += fragment.len();
+///         Ok(())
+///     },
+/// );
+/// assert_eq!(fragments, Ok(2));
+/// assert_eq!(count, 2 + 2 + 100);
+/// ```
+pub fn encode_fragmented<T, F>(
+    value: &T,
+    fragment: &mut [u8],
+    emit: F,
+) -> Result<usize, Error>
+where
+    T: Serialize + ?Sized,
+    F: FnMut(&[u8]) -> Result<(), Error>,
+{
+    if fragment.len() < 2 {
+        return Err(Error::OutOfSpace {
+            needed: 2,
+            available: fragment.len(),
+        });
+    }
+    let mut serializer = Serializer::with_writer(Fragmenter {
+        buf: fragment,
+        len: 1,
+        index: 0,
+        emitted: 0,
+        emit,
+    });
+    value.serialize(&mut serializer)?;
+
+    let mut fragmenter = serializer.into_writer();
+    fragmenter.flush(true)?;
+    Ok(fragmenter.index)
+}
+
+/// Rebuilds values from fragments made by `encode_fragmented`
+///
+/// Values are reassembled in `N` bytes. A fragment arriving out of order
+/// drops the value it belongs to, and the reassembler waits for the first
+/// fragment of the next one.
+///
+/// ```
+/// use fermion::Reassembler;
+///
+/// let mut reassembler = Reassembler::<128>::new();
+/// let mut message = None;
+/// fermion::encode_fragmented(&(7u32, "hello"), &mut [0u8; 4], |fragment| {
+///     if let Some(value) = reassembler.push(fragment)? {
+///         message = Some(fermion::decode::<(u32, &str)>(value)?.0);
+///     }
+///     Ok(())
+/// })
+/// .unwrap();
+/// assert_eq!(message, Some(7));
+/// ```
+#[derive(Debug)]
+pub struct Reassembler<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    // The index of the next fragment, if a value is in progress
+    next: Option<u8>,
+}
+
+impl<const N: usize> Default for Reassembler<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Reassembler<N> {
+    /// Creates a reassembler waiting for a first fragment
+    pub const fn new() -> Self {
+        Reassembler {
+            buf: [0; N],
+            len: 0,
+            next: None,
+        }
+    }
+
+    /// Adds a fragment, returning the encoded value once it is complete
+    ///
+    /// Fails with `Error::InvalidRepresentation` for a fragment out of
+    /// order, and with `Error::OutOfSpace` for a value larger than `N`.
+    pub fn push(&mut self, fragment: &[u8]) -> Result<Option<&[u8]>, Error> {
+        let (header, payload) = match fragment.split_first() {
+            Some(split) => split,
+            None => return Err(Error::InvalidRepresentation),
+        };
+        let index = header & !LAST;
+        if index == 0 {
+            self.len = 0;
+        } else if self.next != Some(index) {
+            self.next = None;
+            return Err(Error::InvalidRepresentation);
+        }
+
+        let end = self.len + payload.len();
+        if end > N {
+            self.next = None;
+            return Err(Error::OutOfSpace {
+                needed: end,
+                available: N,
+            });
+        }
+        self.buf[self.len..end].copy_from_slice(payload);
+        self.len = end;
+
+        if header & LAST != 0 {
+            self.next = None;
+            Ok(Some(&self.buf[..end]))
+        } else {
+            self.next = Some(index + 1);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathological::{Evens, DIGITS};
+    use crate::{decode, encode};
+
+    type Message<'a> = (u32, &'a str, [u16; 4]);
+
+    // 4 + 2 + 390 + 8 = 404 bytes
+    fn message() -> Message<'static> {
+        let text = core::str::from_utf8(&[b'm'; 390]).unwrap();
+        (0xdead_beef, text, [1, 2, 3, 4])
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut reassembler = Reassembler::<512>::new();
+        let mut indices = [0u8; 8];
+        let mut count = 0;
+        let fragments = encode_fragmented(&message(), &mut [0u8; 64], |f| {
+            assert!(f.len() <= 64);
+            indices[count] = f[0];
+            count += 1;
+            if let Some(encoded) = reassembler.push(f)? {
+                assert_eq!(decode::<Message>(encoded), Ok(message()));
+            }
+            Ok(())
+        });
+
+        // 404 bytes in fragments of 63
+        assert_eq!(fragments, Ok(7));
+        assert_eq!(indices, [0, 1, 2, 3, 4, 5, 6 | LAST, 0]);
+    }
+
+    #[test]
+    fn exact_fit() {
+        // 404 bytes in fragments of 101, the last one full
+        let mut lengths = [0usize; 4];
+        let mut count = 0;
+        let fragments = encode_fragmented(&message(), &mut [0u8; 102], |f| {
+            lengths[count] = f.len();
+            count += 1;
+            Ok(())
+        });
+        assert_eq!(fragments, Ok(4));
+        assert_eq!(lengths, [102; 4]);
+    }
+
+    #[test]
+    fn too_many_fragments() {
+        assert_eq!(
+            encode_fragmented(&message(), &mut [0u8; 4], |_| Ok(())),
+            Err(Error::LengthExceeded)
+        );
+    }
+
+    #[test]
+    fn unknown_length() {
+        let mut buf = [0u8; 32];
+        let value = (Evens(&DIGITS), [7u8; 4]);
+        let len = encode(&value, &mut buf).unwrap();
+
+        // the length is patched in before its fragment is emitted
+        let mut reassembler = Reassembler::<32>::new();
+        let mut decoded = false;
+        let fragments = encode_fragmented(&value, &mut [0u8; 10], |f| {
+            if let Some(encoded) = reassembler.push(f)? {
+                assert_eq!(encoded, &buf[..len]);
+                decoded = true;
+            }
+            Ok(())
+        });
+        assert_eq!(fragments, Ok(2));
+        assert!(decoded);
+
+        // the elements spill over to the next fragment
+        assert_eq!(
+            encode_fragmented(&value, &mut [0u8; 4], |_| Ok(())),
+            Err(Error::UnknownLength)
+        );
+    }
+
+    #[test]
+    fn lost_fragment() {
+        let mut fragments = [[0u8; 64]; 7];
+        let mut lengths = [0usize; 7];
+        let mut count = 0;
+        encode_fragmented(&message(), &mut [0u8; 64], |f| {
+            fragments[count][..f.len()].copy_from_slice(f);
+            lengths[count] = f.len();
+            count += 1;
+            Ok(())
+        })
+        .unwrap();
+        let fragment = |i: usize| &fragments[i][..lengths[i]];
+
+        let mut reassembler = Reassembler::<512>::new();
+        assert_eq!(reassembler.push(fragment(0)), Ok(None));
+        assert_eq!(
+            reassembler.push(fragment(2)),
+            Err(Error::InvalidRepresentation)
+        );
+        assert_eq!(
+            reassembler.push(fragment(3)),
+            Err(Error::InvalidRepresentation)
+        );
+
+        // the value is picked up again from its first fragment
+        for i in 0..6 {
+            assert_eq!(reassembler.push(fragment(i)), Ok(None));
+        }
+        let encoded = reassembler.push(fragment(6)).unwrap().unwrap();
+        assert_eq!(decode::<Message>(encoded), Ok(message()));
+    }
+}
@@ -0,0 +1,399 @@
+//! Length-prefixed frames with a CRC trailer, for links that corrupt bytes.
+//!
+//! A frame is the `u16` little-endian length of the encoded value, the
+//! encoded value, and the checksum of both, little-endian. CRCs are computed
+//! bit by bit; the `crc-table` feature trades 512 or 1024 bytes of flash for
+//! table driven ones.
+
+use core::marker::PhantomData;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{decode_whole, encode, Error};
+
+const LEN_SIZE: usize = 2;
+
+/// A checksum protecting a frame
+pub trait Checksum {
+    /// Size of the trailer in bytes, at most 4
+    const SIZE: usize;
+
+    /// Computes the checksum of `bytes`
+    fn checksum(bytes: &[u8]) -> u32;
+}
+
+/// CRC-16/CCITT-FALSE, polynomial `0x1021`
+#[derive(Clone, Copy, Debug)]
+pub struct Crc16;
+
+/// CRC-32 as used by Ethernet and zlib, polynomial `0x04C11DB7`
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32;
+
+const CRC16_POLY: u16 = 0x1021;
+const CRC32_POLY: u32 = 0xedb8_8320;
+
+const fn crc16_byte(mut crc: u16) -> u16 {
+    let mut bit = 0;
+    while bit < 8 {
+        crc = if crc & 0x8000 != 0 {
+            (crc << 1) ^ CRC16_POLY
+        } else {
+            crc << 1
+        };
+        bit += 1;
+    }
+    crc
+}
+
+const fn crc32_byte(mut crc: u32) -> u32 {
+    let mut bit = 0;
+    while bit < 8 {
+        crc = if crc & 1 != 0 {
+            (crc >> 1) ^ CRC32_POLY
+        } else {
+            crc >> 1
+        };
+        bit += 1;
+    }
+    crc
+}
+
+#[cfg(feature = "crc-table")]
+static CRC16_TABLE: [u16; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = crc16_byte((i as u16) << 8);
+        i += 1;
+    }
+    table
+};
+
+#[cfg(feature = "crc-table")]
+static CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = crc32_byte(i as u32);
+        i += 1;
+    }
+    table
+};
+
+impl Checksum for Crc16 {
+    const SIZE: usize = 2;
+
+    fn checksum(bytes: &[u8]) -> u32 {
+        let mut crc = 0xffffu16;
+        for byte in bytes {
+            #[cfg(feature = "crc-table")]
+            {
+                let i = ((crc >> 8) as u8 ^ byte) as usize;
+                crc = (crc << 8) ^ CRC16_TABLE[i];
+            }
+            #[cfg(not(feature = "crc-table"))]
+            {
+                crc = crc16_byte(crc ^ ((*byte as u16) << 8));
+            }
+        }
+        crc as u32
+    }
+}
+
+impl Checksum for Crc32 {
+    const SIZE: usize = 4;
+
+    fn checksum(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for byte in bytes {
+            #[cfg(feature = "crc-table")]
+            {
+                let i = (crc as u8 ^ byte) as usize;
+                crc = (crc >> 8) ^ CRC32_TABLE[i];
+            }
+            #[cfg(not(feature = "crc-table"))]
+            {
+                crc = crc32_byte(crc ^ *byte as u32);
+            }
+        }
+        !crc
+    }
+}
+
+/// Encodes a value into provided buffer as a frame checked by `C`
+///
+/// Returns the size of the frame. On `Error::OutOfSpace`, `needed` is the
+/// full size of the frame.
+pub fn encode_framed<C: Checksum, T: Serialize>(
+    value: &T,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let overhead = LEN_SIZE + C::SIZE;
+    let available = buf.len();
+    let room = available.saturating_sub(overhead);
+    let payload = &mut buf[LEN_SIZE.min(available)..][..room];
+    let len = match encode(value, payload) {
+        Ok(len) => len,
+        Err(Error::OutOfSpace { needed, .. }) => {
+            return Err(Error::OutOfSpace {
+                needed: needed + overhead,
+                available,
+            })
+        }
+        Err(e) => return Err(e),
+    };
+    if len > u16::MAX as usize {
+        return Err(Error::LengthExceeded);
+    }
+    buf[..LEN_SIZE].copy_from_slice(&(len as u16).to_le_bytes());
+
+    let end = LEN_SIZE + len;
+    let checksum = C::checksum(&buf[..end]).to_le_bytes();
+    buf[end..end + C::SIZE].copy_from_slice(&checksum[..C::SIZE]);
+    Ok(end + C::SIZE)
+}
+
+/// Decodes a value from a frame checked by `C` at the start of `buf`
+///
+/// Fails with `Error::ChecksumMismatch` if the frame was corrupted, and
+/// with `Error::TrailingBytes` if the value doesn't fill the frame.
+pub fn decode_framed<'de, C: Checksum, T: Deserialize<'de>>(
+    buf: &'de [u8],
+) -> Result<T, Error> {
+    if buf.len() < LEN_SIZE {
+        return Err(Error::OutOfSpace {
+            needed: LEN_SIZE + C::SIZE,
+            available: buf.len(),
+        });
+    }
+    let len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+    let end = LEN_SIZE + len;
+    if buf.len() < end + C::SIZE {
+        return Err(Error::OutOfSpace {
+            needed: end + C::SIZE,
+            available: buf.len(),
+        });
+    }
+
+    let checksum = C::checksum(&buf[..end]).to_le_bytes();
+    if buf[end..end + C::SIZE] != checksum[..C::SIZE] {
+        return Err(Error::ChecksumMismatch);
+    }
+    decode_whole(&buf[LEN_SIZE..end])
+}
+
+/// The outcome of `FrameDecoder::decode`
+#[derive(Debug, PartialEq)]
+pub enum Decoded<T> {
+    /// The next frame's value
+    Value(T),
+    /// No complete frame is buffered yet
+    NeedMore,
+}
+
+/// Extracts frames checked by `C` from a corrupted byte stream
+///
+/// Input is buffered in `N` bytes, which must fit the largest frame. When a
+/// frame fails its checksum, or claims a length that can't fit, the decoder
+/// skips a byte and looks for the next frame from there, so garbage on the
+/// link costs the frames it hits and no more.
+#[derive(Debug)]
+pub struct FrameDecoder<C, const N: usize> {
+    buf: [u8; N],
+    start: usize,
+    end: usize,
+    discarded: usize,
+    checksum: PhantomData<C>,
+}
+
+impl<C: Checksum, const N: usize> Default for FrameDecoder<C, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Checksum, const N: usize> FrameDecoder<C, N> {
+    /// Creates an empty decoder
+    pub const fn new() -> Self {
+        FrameDecoder {
+            buf: [0; N],
+            start: 0,
+            end: 0,
+            discarded: 0,
+            checksum: PhantomData,
+        }
+    }
+
+    /// Buffers as much of `input` as fits, returning how much was taken
+    pub fn push(&mut self, input: &[u8]) -> usize {
+        if self.start > 0 {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        let taken = input.len().min(N - self.end);
+        self.buf[self.end..self.end + taken].copy_from_slice(&input[..taken]);
+        self.end += taken;
+        taken
+    }
+
+    /// Number of bytes skipped so far while looking for frames
+    pub fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    /// Decodes the next buffered frame
+    ///
+    /// A frame that passes its checksum but doesn't decode as `T` is
+    /// dropped, and its error returned.
+    pub fn decode<'de, T: Deserialize<'de>>(
+        &'de mut self,
+    ) -> Result<Decoded<T>, Error> {
+        loop {
+            let buffered = &self.buf[self.start..self.end];
+            if buffered.len() < LEN_SIZE {
+                return Ok(Decoded::NeedMore);
+            }
+            let len = u16::from_le_bytes([buffered[0], buffered[1]]) as usize;
+            let size = LEN_SIZE + len + C::SIZE;
+            if size > N {
+                self.skip(1);
+                continue;
+            }
+            if buffered.len() < size {
+                return Ok(Decoded::NeedMore);
+            }
+
+            let end = LEN_SIZE + len;
+            let checksum = C::checksum(&buffered[..end]).to_le_bytes();
+            if buffered[end..size] != checksum[..C::SIZE] {
+                self.skip(1);
+                continue;
+            }
+
+            let start = self.start;
+            self.start += size;
+            return match decode_whole(&self.buf[start + LEN_SIZE..][..len]) {
+                Ok(value) => Ok(Decoded::Value(value)),
+                Err(e) => {
+                    self.discarded += size;
+                    Err(e)
+                }
+            };
+        }
+    }
+
+    fn skip(&mut self, count: usize) {
+        self.start += count;
+        self.discarded += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Reading<'a> = (u32, &'a str, Option<i16>);
+
+    const READING: Reading = (7, "temp", Some(-40));
+
+    #[test]
+    fn check_values() {
+        assert_eq!(Crc16::checksum(b"123456789"), 0x29b1);
+        assert_eq!(Crc32::checksum(b"123456789"), 0xcbf4_3926);
+    }
+
+    fn bit_flips<C: Checksum>() {
+        let mut buf = [0u8; 32];
+        let len = encode_framed::<C, _>(&READING, &mut buf).unwrap();
+        assert_eq!(len, 2 + 4 + 6 + 3 + C::SIZE);
+        assert_eq!(decode_framed::<C, Reading>(&buf[..len]), Ok(READING));
+
+        for bit in 0..len * 8 {
+            let mut corrupt = buf;
+            corrupt[bit / 8] ^= 1 << (bit % 8);
+            assert!(decode_framed::<C, Reading>(&corrupt[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn crc16() {
+        bit_flips::<Crc16>();
+    }
+
+    #[test]
+    fn crc32() {
+        bit_flips::<Crc32>();
+    }
+
+    #[test]
+    fn mismatch() {
+        let mut buf = [0u8; 32];
+        let len = encode_framed::<Crc32, _>(&READING, &mut buf).unwrap();
+        buf[4] ^= 0x10;
+        assert_eq!(
+            decode_framed::<Crc32, Reading>(&buf[..len]),
+            Err(Error::ChecksumMismatch)
+        );
+    }
+
+    #[test]
+    fn resynchronize() {
+        let garbage: [&[u8]; 4] = [&[], &[0xff; 7], &[3, 0, 1, 2], &[0, 0]];
+        let mut stream = [0u8; 256];
+        let mut len = 0;
+        for junk in garbage {
+            stream[len..len + junk.len()].copy_from_slice(junk);
+            len += junk.len();
+            len += encode_framed::<Crc16, _>(&READING, &mut stream[len..])
+                .unwrap();
+        }
+
+        let mut decoder = FrameDecoder::<Crc16, 32>::new();
+        let mut decoded = 0;
+        for chunk in stream[..len].chunks(5) {
+            assert_eq!(decoder.push(chunk), chunk.len());
+            while let Decoded::Value(value) =
+                decoder.decode::<Reading>().unwrap()
+            {
+                assert_eq!(value, READING);
+                decoded += 1;
+            }
+        }
+        assert_eq!(decoded, 4);
+        assert_eq!(decoder.discarded(), 7 + 4 + 2);
+    }
+
+    #[test]
+    fn undecodable() {
+        let mut buf = [0u8; 32];
+        let len = encode_framed::<Crc16, _>(&(1u8, 2u8), &mut buf).unwrap();
+
+        let mut decoder = FrameDecoder::<Crc16, 32>::new();
+        decoder.push(&buf[..len]);
+        decoder.push(&buf[..len]);
+        assert_eq!(decoder.decode::<u8>(), Err(Error::TrailingBytes));
+        assert_eq!(decoder.decode::<(u8, u8)>(), Ok(Decoded::Value((1, 2))));
+        assert_eq!(decoder.decode::<(u8, u8)>(), Ok(Decoded::NeedMore));
+    }
+
+    #[test]
+    fn out_of_space() {
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            encode_framed::<Crc32, _>(&READING, &mut buf),
+            Err(Error::OutOfSpace {
+                needed: 19,
+                available: 16
+            })
+        );
+        assert_eq!(
+            encode_framed::<Crc32, _>(&READING, &mut buf[..3]),
+            Err(Error::OutOfSpace {
+                needed: 19,
+                available: 3
+            })
+        );
+    }
+}
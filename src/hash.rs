@@ -0,0 +1,287 @@
+use core::hash::Hasher;
+
+use serde::Serialize;
+
+use crate::ser::{Serializer, Writer};
+use crate::Error;
+
+/// Lengths recorded per pass, of sequences of unknown length and
+/// `collect_str`
+const WINDOW: usize = 32;
+
+/// Feeds the output to a sink that can't go back, up to the first length
+/// it doesn't know yet. It records the lengths patched in from there on,
+/// for the next pass to continue with.
+struct Pass<'a, F> {
+    feed: &'a mut F,
+    // Output bytes fed by the passes so far, and this pass's position
+    fed: usize,
+    pos: usize,
+    // Index of the next reserved length, the first one in `lens`, and how
+    // many `lens` holds
+    next: usize,
+    first: usize,
+    known: usize,
+    lens: [[u8; 2]; WINDOW],
+    // The lengths recorded for the next pass
+    recorded: [[u8; 2]; WINDOW],
+    unknown: usize,
+}
+
+impl<F: FnMut(&[u8]) -> Result<(), Error>> Pass<'_, F> {
+    /// Feeds the part of `bytes`, written at the position of the pass, the
+    /// passes before didn't
+    fn advance(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.pos + bytes.len();
+        if self.unknown == 0 && end > self.fed {
+            (self.feed)(&bytes[self.fed.saturating_sub(self.pos)..])?;
+            self.fed = end;
+        }
+        self.pos = end;
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Result<(), Error>> Writer for Pass<'_, F> {
+    #[inline(always)]
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.advance(bytes)
+    }
+
+    fn reserve(&mut self, len: usize) -> Result<usize, Error> {
+        let at = self.next;
+        self.next += 1;
+        match at.checked_sub(self.first) {
+            // fed by the passes before
+            None => self.pos += len,
+            Some(i) if i < self.known => {
+                let known = self.lens[i];
+                self.advance(&known[..len])?;
+            }
+            Some(i) => {
+                if i - self.known < WINDOW {
+                    self.unknown += 1;
+                }
+                self.pos += len;
+            }
+        }
+        Ok(at)
+    }
+
+    fn patch(&mut self, at: usize, bytes: &[u8]) -> Result<(), Error> {
+        let recorded = at
+            .checked_sub(self.first + self.known)
+            .and_then(|i| self.recorded.get_mut(i));
+        if let Some(len) = recorded {
+            len[..bytes.len()].copy_from_slice(bytes);
+        }
+        Ok(())
+    }
+}
+
+/// Passes the encoding of `value` to `feed` in pieces
+///
+/// A value is serialized once, and again for every 32 lengths patched in
+/// after they are written. Each pass feeds the output up to the first
+/// length not known yet, and records the next 32 for the pass after it.
+/// An error returned by `feed` stops the encoding.
+pub(crate) fn feed_encoded<T, F>(value: &T, mut feed: F) -> Result<(), Error>
+where
+    T: Serialize + ?Sized,
+    F: FnMut(&[u8]) -> Result<(), Error>,
+{
+    let mut pass = Pass {
+        feed: &mut feed,
+        fed: 0,
+        pos: 0,
+        next: 0,
+        first: 0,
+        known: 0,
+        lens: [[0; 2]; WINDOW],
+        recorded: [[0; 2]; WINDOW],
+        unknown: 0,
+    };
+    loop {
+        value.serialize(&mut Serializer::with_writer(&mut pass))?;
+        if pass.unknown == 0 {
+            return Ok(());
+        }
+        pass.first += pass.known;
+        pass.known = pass.unknown;
+        pass.lens = pass.recorded;
+        pass.pos = 0;
+        pass.next = 0;
+        pass.unknown = 0;
+    }
+}
+
+/// Feeds the encoding of `value` straight into `hasher`, without a buffer
+///
+/// The bytes are passed to `Hasher::write` in pieces, which for streaming
+/// hashers such as `DefaultHasher` gives the same result as writing the
+/// output of `encode` at once. Values holding sequences of unknown length
+/// or `collect_str` are serialized again for every 32 of those, as their
+/// lengths are only known after the fact.
+pub fn hash_encoded<T, H>(value: &T, hasher: &mut H) -> Result<(), Error>
+where
+    T: Serialize + ?Sized,
+    H: Hasher,
+{
+    feed_encoded(value, |bytes| {
+        hasher.write(bytes);
+        Ok(())
+    })
+}
+
+/// Feeds the encoding of `value` straight into a cryptographic digest
+///
+/// Produces the same digest as hashing the output of `encode`, without
+/// holding the encoded value in memory. As with `hash_encoded`, values
+/// with lengths patched in are serialized more than once.
+#[cfg(feature = "digest")]
+pub fn digest_encoded<T, D>(value: &T, digest: &mut D) -> Result<(), Error>
+where
+    T: Serialize + ?Sized,
+    D: digest::Update,
+{
+    feed_encoded(value, |bytes| {
+        digest.update(bytes);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode;
+
+    use crate::pathological::{Evens, Unsized, DIGITS};
+    use core::cell::Cell;
+
+    type Transaction<'a> = (u64, &'a str, Option<[u32; 3]>, (char, i16));
+
+    const TX: Transaction = (42, "transfer", Some([1, 2, 3]), ('⚑', -7));
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn hasher() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let mut buf = [0u8; 64];
+        let len = encode(&TX, &mut buf).unwrap();
+
+        let mut expected = DefaultHasher::new();
+        expected.write(&buf[..len]);
+
+        let mut hasher = DefaultHasher::new();
+        hash_encoded(&TX, &mut hasher).unwrap();
+
+        assert_eq!(hasher.finish(), expected.finish());
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn digest() {
+        use sha2::{Digest, Sha256};
+
+        let mut buf = [0u8; 64];
+        let len = encode(&TX, &mut buf).unwrap();
+
+        let mut sha = Sha256::new();
+        digest_encoded(&TX, &mut sha).unwrap();
+
+        assert_eq!(sha.finalize(), Sha256::digest(&buf[..len]));
+    }
+
+    /// Formats as its number of items, through `collect_str`
+    #[derive(Clone, Copy)]
+    struct Items(u32);
+
+    impl core::fmt::Display for Items {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            write!(f, "{} items", self.0)
+        }
+    }
+
+    impl Serialize for Items {
+        fn serialize<S: serde::Serializer>(
+            &self,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+
+    /// Records the first bytes hashed
+    struct Recorder {
+        buf: [u8; 1024],
+        len: usize,
+    }
+
+    impl Default for Recorder {
+        fn default() -> Self {
+            Recorder {
+                buf: [0; 1024],
+                len: 0,
+            }
+        }
+    }
+
+    impl Hasher for Recorder {
+        fn write(&mut self, bytes: &[u8]) {
+            let end = self.len + bytes.len();
+            if let Some(dst) = self.buf.get_mut(self.len..end) {
+                dst.copy_from_slice(bytes);
+            }
+            self.len = end;
+        }
+
+        fn finish(&self) -> u64 {
+            0
+        }
+    }
+
+    #[test]
+    fn patched_lengths() {
+        fn assert_fed<T: Serialize>(value: &T) {
+            let mut buf = [0u8; 1024];
+            let len = encode(value, &mut buf).unwrap();
+
+            let mut hasher = Recorder::default();
+            hash_encoded(value, &mut hasher).unwrap();
+            assert_eq!(&hasher.buf[..hasher.len], &buf[..len]);
+        }
+
+        assert_fed(&(Items(7), [Items(300), Items(12)], 5u8));
+
+        // more lengths than a pass records, nested in each other
+        let items = [Items(1); 20];
+        let nested = [Unsized(&items[..]); 3];
+        assert_fed(&(Evens(&DIGITS), Unsized(&nested), 3u8, [Items(300); 2]));
+    }
+
+    #[test]
+    fn passes() {
+        /// Counts how often it is serialized
+        struct Counted<'a, T>(&'a Cell<usize>, T);
+
+        impl<T: Serialize> Serialize for Counted<'_, T> {
+            fn serialize<S: serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                self.0.set(self.0.get() + 1);
+                self.1.serialize(serializer)
+            }
+        }
+
+        let passes = Cell::new(0);
+        hash_encoded(&Counted(&passes, TX), &mut Recorder::default()).unwrap();
+        assert_eq!(passes.get(), 1);
+
+        let passes = Cell::new(0);
+        let value = Counted(&passes, &[Items(1); 33][..]);
+        hash_encoded(&value, &mut Recorder::default()).unwrap();
+        assert_eq!(passes.get(), 3);
+    }
+}
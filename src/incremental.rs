@@ -0,0 +1,323 @@
+//! Decoding values from input arriving in chunks.
+
+use serde::de::DeserializeOwned;
+
+use crate::de::Deserializer;
+use crate::Error;
+
+/// The outcome of `IncrementalDecoder::decode`
+#[derive(Debug, PartialEq)]
+pub enum Progress<T> {
+    /// The value was complete
+    Complete(T),
+    /// At least `needed` more bytes must arrive to complete the value
+    Incomplete {
+        /// Bytes missing from the input
+        needed: usize,
+    },
+}
+
+/// Values `IncrementalDecoder` decodes one top-level field at a time
+///
+/// Implemented for tuples and arrays. A struct encodes the same as the
+/// tuple of its fields, so it can be decoded as that tuple and converted.
+pub trait Resumable: Sized {
+    /// The fields decoded so far
+    type Partial: Default;
+
+    /// Decodes the next field into `partial`, or takes the value out of it
+    /// once every field is there
+    fn decode_next(
+        partial: &mut Self::Partial,
+        deserializer: &mut Deserializer,
+    ) -> Result<Option<Self>, Error>;
+}
+
+macro_rules! impl_tuple {
+    ($($t:ident $i:tt)+) => {
+        impl<$($t: DeserializeOwned),+> Resumable for ($($t,)+) {
+            type Partial = ($(Option<$t>,)+);
+
+            fn decode_next(
+                partial: &mut Self::Partial,
+                deserializer: &mut Deserializer,
+            ) -> Result<Option<Self>, Error> {
+                $(
+                    if partial.$i.is_none() {
+                        partial.$i = Some($t::deserialize(deserializer)?);
+                        return Ok(None);
+                    }
+                )+
+                Ok(Some(($(partial.$i.take().unwrap(),)+)))
+            }
+        }
+    };
+}
+
+impl_tuple!(A 0);
+impl_tuple!(A 0 B 1);
+impl_tuple!(A 0 B 1 C 2);
+impl_tuple!(A 0 B 1 C 2 D 3);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5 G 6);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8 J 9);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8 J 9 K 10);
+impl_tuple!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8 J 9 K 10 L 11);
+
+/// The elements of an array decoded so far
+#[derive(Debug)]
+pub struct PartialArray<T, const N: usize> {
+    items: [Option<T>; N],
+    len: usize,
+}
+
+impl<T, const N: usize> Default for PartialArray<T, N> {
+    fn default() -> Self {
+        PartialArray {
+            items: core::array::from_fn(|_| None),
+            len: 0,
+        }
+    }
+}
+
+impl<T: DeserializeOwned, const N: usize> Resumable for [T; N] {
+    type Partial = PartialArray<T, N>;
+
+    fn decode_next(
+        partial: &mut Self::Partial,
+        deserializer: &mut Deserializer,
+    ) -> Result<Option<Self>, Error> {
+        if partial.len < N {
+            partial.items[partial.len] = Some(T::deserialize(deserializer)?);
+            partial.len += 1;
+            return Ok(None);
+        }
+        let items = core::mem::take(partial).items;
+        Ok(Some(items.map(|item| item.unwrap())))
+    }
+}
+
+/// Decodes values whose encoding arrives in chunks, such as from DMA
+///
+/// Chunks are buffered in `N` bytes. Each top-level field of `T` is kept
+/// once decoded, along with where the next one starts, so a decode that
+/// runs out of input continues from the first field still missing once
+/// more has arrived. A field cut short is parsed again from its start, but
+/// only when the bytes it stopped at are there.
+///
+/// ```
+/// use fermion::{IncrementalDecoder, Progress};
+///
+/// let mut buf = [0u8; 6];
+/// fermion::encode(&(7u16, 9u32), &mut buf).unwrap();
+///
+/// let mut decoder = IncrementalDecoder::<(u16, u32), 16>::new();
+/// decoder.push(&buf[..3]);
+/// assert_eq!(decoder.decode(), Ok(Progress::Incomplete { needed: 3 }));
+/// decoder.push(&buf[3..]);
+/// assert_eq!(decoder.decode(), Ok(Progress::Complete((7, 9))));
+/// ```
+pub struct IncrementalDecoder<T: Resumable, const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    // Start of the first field not decoded yet
+    checkpoint: usize,
+    // Buffered bytes the last attempt found missing
+    needed: usize,
+    partial: T::Partial,
+}
+
+impl<T: Resumable, const N: usize> core::fmt::Debug
+    for IncrementalDecoder<T, N>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("IncrementalDecoder")
+            .field("buf", &&self.buf[..self.len])
+            .field("checkpoint", &self.checkpoint)
+            .field("needed", &self.needed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Resumable, const N: usize> Default for IncrementalDecoder<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Resumable, const N: usize> IncrementalDecoder<T, N> {
+    /// Creates an empty decoder
+    pub fn new() -> Self {
+        IncrementalDecoder {
+            buf: [0; N],
+            len: 0,
+            checkpoint: 0,
+            needed: 0,
+            partial: T::Partial::default(),
+        }
+    }
+
+    /// Buffers as much of `input` as fits, returning how much was taken
+    pub fn push(&mut self, input: &[u8]) -> usize {
+        let taken = input.len().min(N - self.len);
+        self.buf[self.len..self.len + taken].copy_from_slice(&input[..taken]);
+        self.len += taken;
+        taken
+    }
+
+    /// Discards all buffered input and decoded fields, such as after a
+    /// decoding error
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.checkpoint = 0;
+        self.needed = 0;
+        self.partial = T::Partial::default();
+    }
+
+    /// Decodes a value from the buffered input, if it is complete
+    ///
+    /// Input following the value stays buffered for the next one. Fails with
+    /// `Error::OutOfSpace` if the value can't fit the buffer.
+    pub fn decode(&mut self) -> Result<Progress<T>, Error> {
+        if self.len < self.needed {
+            return Ok(Progress::Incomplete {
+                needed: self.needed - self.len,
+            });
+        }
+
+        loop {
+            let input = &self.buf[self.checkpoint..self.len];
+            let mut deserializer = Deserializer::new(input);
+            match T::decode_next(&mut self.partial, &mut deserializer) {
+                Ok(None) => self.checkpoint += deserializer.position(),
+                Ok(Some(value)) => {
+                    let consumed = self.checkpoint + deserializer.position();
+                    self.buf.copy_within(consumed..self.len, 0);
+                    self.len -= consumed;
+                    self.checkpoint = 0;
+                    self.needed = 0;
+                    return Ok(Progress::Complete(value));
+                }
+                Err(Error::OutOfSpace { needed, .. }) => {
+                    let needed = self.checkpoint + needed;
+                    if needed > N {
+                        return Err(Error::OutOfSpace {
+                            needed,
+                            available: N,
+                        });
+                    }
+                    self.needed = needed;
+                    return Ok(Progress::Incomplete {
+                        needed: needed - self.len,
+                    });
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode;
+
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use serde::{Deserialize, Serialize};
+
+    static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+    /// A `u8` counting the attempts to decode it
+    #[derive(Debug, PartialEq, Serialize)]
+    struct Counted(u8);
+
+    impl<'de> Deserialize<'de> for Counted {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            ATTEMPTS.fetch_add(1, Ordering::Relaxed);
+            u8::deserialize(deserializer).map(Counted)
+        }
+    }
+
+    type Sample = (Counted, Option<[i32; 2]>, char, bool);
+
+    const SAMPLES: [Sample; 3] = [
+        (Counted(1), Some([-1, 2]), '⚑', true),
+        (Counted(2), None, 'a', false),
+        (Counted(3), Some([0, i32::MAX]), '\u{10ffff}', true),
+    ];
+
+    #[test]
+    fn byte_by_byte() {
+        let mut stream = [0u8; 64];
+        let mut len = 0;
+        for sample in &SAMPLES {
+            len += encode(sample, &mut stream[len..]).unwrap();
+        }
+
+        let mut decoder = IncrementalDecoder::<Sample, 16>::new();
+        let mut decoded = 0;
+        for byte in stream[..len].chunks(1) {
+            assert_eq!(decoder.push(byte), 1);
+            if let Progress::Complete(sample) = decoder.decode().unwrap() {
+                assert_eq!(sample, SAMPLES[decoded]);
+                decoded += 1;
+            }
+        }
+        assert_eq!(decoded, 3);
+        // the first field of each sample is never parsed again
+        assert_eq!(ATTEMPTS.load(Ordering::Relaxed), 3);
+
+        // nothing left over
+        assert_eq!(decoder.decode(), Ok(Progress::Incomplete { needed: 1 }));
+    }
+
+    #[test]
+    fn resumes() {
+        let value = [(1u8, 'a'), (2, '⚑'), (3, 'b')];
+        let mut buf = [0u8; 32];
+        let len = encode(&value, &mut buf).unwrap();
+
+        // each element is decoded once it is there, the second one cut
+        // short in its `char`
+        let mut decoder = IncrementalDecoder::<[(u8, char); 3], 16>::new();
+        decoder.push(&buf[..4]);
+        assert_eq!(decoder.decode(), Ok(Progress::Incomplete { needed: 2 }));
+        assert_eq!(decoder.checkpoint, 2);
+        assert_eq!(decoder.partial.len, 1);
+
+        decoder.push(&buf[4..6]);
+        assert_eq!(decoder.decode(), Ok(Progress::Incomplete { needed: 1 }));
+        assert_eq!(decoder.checkpoint, 6);
+        assert_eq!(decoder.partial.len, 2);
+
+        decoder.push(&buf[6..len]);
+        decoder.push(&[0xff]);
+        assert_eq!(decoder.decode(), Ok(Progress::Complete(value)));
+        assert_eq!(decoder.checkpoint, 0);
+        assert_eq!(&decoder.buf[..decoder.len], [0xff]);
+
+        decoder.reset();
+        decoder.push(&[1, 0xff]);
+        assert_eq!(decoder.decode(), Err(Error::InvalidRepresentation));
+    }
+
+    #[test]
+    fn too_large() {
+        let mut decoder =
+            IncrementalDecoder::<(u8, Option<[i32; 2]>), 4>::new();
+        decoder.push(&[1, 1]);
+        assert_eq!(
+            decoder.decode(),
+            Err(Error::OutOfSpace {
+                needed: 6,
+                available: 4
+            })
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! Encoding to and decoding from `embedded-io` streams.
+//!
+//! Decoding reads exactly the bytes the value needs, as the decoder finds
+//! them missing, so a stream can carry values back to back without framing.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::hash::feed_encoded;
+use crate::{decode, Error};
+
+/// Errors of encoding to or decoding from a stream
+#[derive(Debug, PartialEq)]
+pub enum IoError<E> {
+    /// The stream failed
+    Io(E),
+    /// The stream ended before the value did
+    UnexpectedEof,
+    /// The value failed to encode or decode
+    Encoding(Error),
+}
+
+impl<E> From<Error> for IoError<E> {
+    fn from(e: Error) -> Self {
+        IoError::Encoding(e)
+    }
+}
+
+impl<E> From<embedded_io::ReadExactError<E>> for IoError<E> {
+    fn from(e: embedded_io::ReadExactError<E>) -> Self {
+        match e {
+            embedded_io::ReadExactError::UnexpectedEof => {
+                IoError::UnexpectedEof
+            }
+            embedded_io::ReadExactError::Other(e) => IoError::Io(e),
+        }
+    }
+}
+
+/// Returns how far `buf` must be filled for `decode` to make progress, or
+/// the value if it is complete
+fn try_decode<T: DeserializeOwned>(
+    buf: &[u8],
+    filled: usize,
+) -> Result<Result<T, usize>, Error> {
+    match decode(&buf[..filled]) {
+        Ok(value) => Ok(Ok(value)),
+        Err(Error::OutOfSpace { needed, .. }) if needed <= buf.len() => {
+            Ok(Err(needed))
+        }
+        Err(Error::OutOfSpace { needed, .. }) => Err(Error::OutOfSpace {
+            needed,
+            available: buf.len(),
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Encodes a value straight to a blocking stream
+///
+/// Every field is written as it is encoded, so `out` should buffer if small
+/// writes are costly. Values holding sequences of unknown length or
+/// `collect_str` are serialized again for every 32 of those, to write
+/// their lengths before them. Returns the number of bytes written.
+pub fn encode_to<W, T>(
+    out: &mut W,
+    value: &T,
+) -> Result<usize, IoError<W::Error>>
+where
+    W: embedded_io::Write,
+    T: Serialize + ?Sized,
+{
+    let mut written = 0;
+    let mut error = None;
+    let result = feed_encoded(value, |bytes| {
+        out.write_all(bytes).map_err(|e| {
+            error = Some(e);
+            Error::Custom
+        })?;
+        written += bytes.len();
+        Ok(())
+    });
+    match (result, error) {
+        (_, Some(e)) => Err(IoError::Io(e)),
+        (Err(e), None) => Err(IoError::Encoding(e)),
+        (Ok(()), None) => Ok(written),
+    }
+}
+
+/// Decodes a value from a blocking stream, using `buf` to hold its encoding
+///
+/// Only the bytes of the value are read from `input`.
+pub fn decode_from<R, T>(
+    input: &mut R,
+    buf: &mut [u8],
+) -> Result<T, IoError<R::Error>>
+where
+    R: embedded_io::Read,
+    T: DeserializeOwned,
+{
+    let mut filled = 0;
+    loop {
+        match try_decode(buf, filled)? {
+            Ok(value) => return Ok(value),
+            Err(needed) => {
+                input.read_exact(&mut buf[filled..needed])?;
+                filled = needed;
+            }
+        }
+    }
+}
+
+/// Encodes a value to an async stream, through `buf`
+///
+/// serde can't wait for the stream in the middle of a value, so it is
+/// encoded to `buf` first. Returns the number of bytes written.
+#[cfg(feature = "embedded-io-async")]
+pub async fn encode_to_async<W, T>(
+    out: &mut W,
+    value: &T,
+    buf: &mut [u8],
+) -> Result<usize, IoError<W::Error>>
+where
+    W: embedded_io_async::Write,
+    T: Serialize,
+{
+    let len = crate::encode(value, buf)?;
+    out.write_all(&buf[..len]).await.map_err(IoError::Io)?;
+    Ok(len)
+}
+
+/// Decodes a value from an async stream, using `buf` to hold its encoding
+///
+/// Only the bytes of the value are read from `input`.
+#[cfg(feature = "embedded-io-async")]
+pub async fn decode_from_async<R, T>(
+    input: &mut R,
+    buf: &mut [u8],
+) -> Result<T, IoError<R::Error>>
+where
+    R: embedded_io_async::Read,
+    T: DeserializeOwned,
+{
+    let mut filled = 0;
+    loop {
+        match try_decode(buf, filled)? {
+            Ok(value) => return Ok(value),
+            Err(needed) => {
+                input.read_exact(&mut buf[filled..needed]).await?;
+                filled = needed;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathological::{Evens, DIGITS};
+
+    type Reading = (u16, Option<[i32; 2]>, char);
+
+    const READINGS: [Reading; 2] = [(1, Some([-5, 7]), '⚑'), (2, None, 'x')];
+
+    /// An in-memory stream, reading and writing at most 3 bytes at a time
+    struct Pipe {
+        data: [u8; 64],
+        written: usize,
+        read: usize,
+    }
+
+    impl Pipe {
+        fn new() -> Self {
+            Pipe {
+                data: [0; 64],
+                written: 0,
+                read: 0,
+            }
+        }
+
+        fn read_some(&mut self, buf: &mut [u8]) -> usize {
+            let len = buf.len().min(3).min(self.written - self.read);
+            buf[..len].copy_from_slice(&self.data[self.read..][..len]);
+            self.read += len;
+            len
+        }
+
+        fn write_some(&mut self, buf: &[u8]) -> Result<usize, Full> {
+            let len = buf.len().min(3).min(self.data.len() - self.written);
+            if len == 0 && !buf.is_empty() {
+                return Err(Full);
+            }
+            self.data[self.written..][..len].copy_from_slice(&buf[..len]);
+            self.written += len;
+            Ok(len)
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Full;
+
+    impl embedded_io::Error for Full {
+        fn kind(&self) -> embedded_io::ErrorKind {
+            embedded_io::ErrorKind::OutOfMemory
+        }
+    }
+
+    impl embedded_io::ErrorType for Pipe {
+        type Error = Full;
+    }
+
+    mod blocking {
+        use super::*;
+
+        impl embedded_io::Read for Pipe {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize, Full> {
+                Ok(self.read_some(buf))
+            }
+        }
+
+        impl embedded_io::Write for Pipe {
+            fn write(&mut self, buf: &[u8]) -> Result<usize, Full> {
+                self.write_some(buf)
+            }
+
+            fn flush(&mut self) -> Result<(), Full> {
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn back_to_back() {
+            let mut pipe = Pipe::new();
+            for reading in &READINGS {
+                let len = encode_to(&mut pipe, reading).unwrap();
+                assert_eq!(len, crate::encoded_size(reading).unwrap());
+            }
+
+            let mut buf = [0u8; 16];
+            for reading in &READINGS {
+                assert_eq!(decode_from(&mut pipe, &mut buf), Ok(*reading));
+            }
+            assert_eq!(
+                decode_from::<_, Reading>(&mut pipe, &mut buf),
+                Err(IoError::UnexpectedEof)
+            );
+        }
+
+        #[test]
+        fn unknown_length() {
+            let value = (7u8, Evens(&DIGITS), 'x');
+            let mut pipe = Pipe::new();
+            let len = encode_to(&mut pipe, &value).unwrap();
+
+            let mut buf = [0u8; 16];
+            assert_eq!(crate::encode(&value, &mut buf), Ok(len));
+            assert_eq!(&pipe.data[..pipe.written], &buf[..len]);
+        }
+
+        #[test]
+        fn errors() {
+            let mut pipe = Pipe::new();
+            pipe.written = 60;
+            assert_eq!(
+                encode_to(&mut pipe, &READINGS[0]),
+                Err(IoError::Io(Full))
+            );
+
+            let mut pipe = Pipe::new();
+            encode_to(&mut pipe, &READINGS[0]).unwrap();
+            assert_eq!(
+                decode_from::<_, Reading>(&mut pipe, &mut [0u8; 4]),
+                Err(IoError::Encoding(Error::OutOfSpace {
+                    needed: 7,
+                    available: 4
+                }))
+            );
+        }
+    }
+
+    #[cfg(feature = "embedded-io-async")]
+    mod nonblocking {
+        use super::*;
+
+        use core::future::Future;
+        use core::pin::pin;
+        use core::task::{Context, Poll, Waker};
+
+        impl embedded_io_async::Read for Pipe {
+            async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Full> {
+                Ok(self.read_some(buf))
+            }
+        }
+
+        impl embedded_io_async::Write for Pipe {
+            async fn write(&mut self, buf: &[u8]) -> Result<usize, Full> {
+                self.write_some(buf)
+            }
+        }
+
+        /// Polls a future that never waits to completion
+        fn ready<F: Future>(future: F) -> F::Output {
+            let mut context = Context::from_waker(Waker::noop());
+            match pin!(future).poll(&mut context) {
+                Poll::Ready(output) => output,
+                Poll::Pending => panic!("the pipe never waits"),
+            }
+        }
+
+        #[test]
+        fn back_to_back() {
+            let mut pipe = Pipe::new();
+            let mut buf = [0u8; 16];
+            for reading in &READINGS {
+                ready(encode_to_async(&mut pipe, reading, &mut buf)).unwrap();
+            }
+
+            for reading in &READINGS {
+                let decoded = ready(decode_from_async(&mut pipe, &mut buf));
+                assert_eq!(decoded, Ok(*reading));
+            }
+        }
+    }
+}
@@ -6,17 +6,84 @@
 
 use serde::{Deserialize, Serialize};
 
+mod bounded;
+mod cobs;
+mod codec;
 mod de;
+mod evolving;
+#[cfg(feature = "heapless")]
+mod fixed;
+mod fragment;
+mod frame;
+mod hash;
+mod incremental;
+#[cfg(feature = "embedded-io")]
+mod io;
+mod lzss;
+mod max_size;
+mod ordered;
+mod packed;
+mod ring;
 mod ser;
+#[cfg(feature = "tokio-codec")]
+mod tokio_codec;
+pub mod with;
+mod zero_pack;
+
+pub use bounded::{BoundedBytes, BoundedStr};
+pub use cobs::{decode_cobs, encode_cobs, CobsAccumulator, FeedResult};
+pub use codec::{Decode, Encode};
+pub use de::Deserializer;
+pub use evolving::Evolving;
+#[cfg(feature = "heapless")]
+pub use fixed::encode_heapless;
+pub use fragment::{encode_fragmented, Reassembler};
+pub use frame::{
+    decode_framed, encode_framed, Checksum, Crc16, Crc32, Decoded, FrameDecoder,
+};
+#[cfg(feature = "digest")]
+pub use hash::digest_encoded;
+pub use hash::hash_encoded;
+pub use incremental::{IncrementalDecoder, PartialArray, Progress, Resumable};
+#[cfg(feature = "embedded-io")]
+pub use io::{decode_from, encode_to, IoError};
+#[cfg(feature = "embedded-io-async")]
+pub use io::{decode_from_async, encode_to_async};
+pub use lzss::{compress, decode_compressed, decompress, encode_compressed};
+pub use max_size::MaxSize;
+pub use ordered::{
+    decode_ordered, decode_ordered_with_scratch, encode_ordered,
+    OrderedDeserializer, OrderedSerializer,
+};
+pub use packed::{
+    decode_packed, encode_packed, packed_size, Bits, PackedDeserializer,
+    PackedInt, PackedSeqSerializer, PackedSerializer,
+};
+pub use ring::{RingBuffer, RingWriter};
+pub use ser::{SeqSerializer, Serializer, SizeCounter, SliceWriter, Writer};
+#[cfg(feature = "tokio-codec")]
+pub use tokio_codec::FermionCodec;
+pub use zero_pack::{
+    decode_zero_packed, encode_zero_packed, pack_zeros, unpack_zeros,
+};
+
+#[cfg(feature = "derive")]
+pub use fermion_derive::{Decode, Encode};
 
 #[cfg(test)]
+#[allow(clippy::large_const_arrays, clippy::legacy_numeric_constants)]
 mod pathological;
 
 /// Errors that might occur during serialization/deserialization
 #[derive(Debug, PartialEq)]
 pub enum Error {
     /// Insufficient space in buffer
-    OutOfSpace,
+    OutOfSpace {
+        /// Size the buffer needs to be
+        needed: usize,
+        /// Size of the buffer
+        available: usize,
+    },
     /// Invalid byte encoding
     InvalidRepresentation,
     /// Enum with more than
@@ -25,6 +92,17 @@ pub enum Error {
     NotSupported,
     /// A byte slice or a `str` exceeded maximum length
     LengthExceeded,
+    /// A decoding resource limit was exceeded
+    LimitExceeded,
+    /// A length only known after writing can't be patched into the output
+    UnknownLength,
+    /// Input left over after decoding a value canonically
+    TrailingBytes,
+    /// A borrowed value straddles two input segments, without scratch space
+    /// to copy it to
+    SegmentBoundary,
+    /// A frame's checksum doesn't match its contents
+    ChecksumMismatch,
     /// Custom error
     Custom,
 }
@@ -48,12 +126,117 @@ impl serde::de::Error for Error {
     fn custom<T>(_msg: T) -> Error {
         Error::Custom
     }
+
+    /// Serde reports both sequences longer than a value holds and shorter
+    /// than it needs through `invalid_length`. The sequence accesses turn
+    /// the latter, raised after the sequence ran out, back into
+    /// `Error::Custom`.
+    fn invalid_length(_len: usize, _exp: &dyn serde::de::Expected) -> Error {
+        Error::LengthExceeded
+    }
+}
+
+/// Fails decoding a value of length `len` longer than its bound, with
+/// `Error::LengthExceeded` in fermion
+pub(crate) fn length_exceeded<E: serde::de::Error>(len: usize) -> E {
+    E::invalid_length(len, &"a length within the bound")
+}
+
+/// Undoes `Error::LengthExceeded` raised by a visitor after its sequence
+/// reported no more elements, a sequence too short rather than too long
+pub(crate) fn too_short<T>(
+    value: Result<T, Error>,
+    ended: bool,
+) -> Result<T, Error> {
+    match value {
+        Err(Error::LengthExceeded) if ended => Err(Error::Custom),
+        value => value,
+    }
+}
+
+/// Resource limits enforced while decoding
+///
+/// The default is unlimited, use struct update syntax to set individual
+/// limits:
+///
+/// ```
+/// let limits = fermion::Limits {
+///     max_depth: 8,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    /// Maximum nesting depth of options, sequences, tuples, structs and enums
+    pub max_depth: usize,
+    /// Maximum total number of sequence elements
+    pub max_elements: usize,
+    /// Maximum number of bytes consumed
+    pub max_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: usize::MAX,
+            max_elements: usize::MAX,
+            max_bytes: usize::MAX,
+        }
+    }
 }
 
 /// Encodes a value into provided buffer
-pub fn encode<T: Serialize>(value: &T, buf: &mut [u8]) -> Result<(), Error> {
+///
+/// Returns the number of bytes written. A failed encode commits nothing,
+/// though bytes of `buf` may have been overwritten; use `encode_presized`
+/// to leave it untouched. On `Error::OutOfSpace`, `needed` is the full size
+/// of the encoded value.
+pub fn encode<T: Serialize>(value: &T, buf: &mut [u8]) -> Result<usize, Error> {
     let mut serializer = ser::Serializer::new(buf);
-    value.serialize(&mut serializer)
+    match value.serialize(&mut serializer) {
+        Ok(()) => Ok(serializer.into_writer().len()),
+        Err(Error::OutOfSpace { available, .. }) => Err(Error::OutOfSpace {
+            needed: encoded_size(value)?,
+            available,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the number of bytes `value` encodes to
+pub fn encoded_size<T: Serialize>(value: &T) -> Result<usize, Error> {
+    let mut serializer = ser::Serializer::with_writer(SizeCounter::default());
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_writer().size())
+}
+
+/// Encodes a value into provided buffer in two passes
+///
+/// The encoded size is computed first and checked against `buf` once, so
+/// the value is then written without checking for space field by field.
+/// Returns the number of bytes written, or leaves `buf` untouched if it is
+/// too small.
+///
+/// # Safety
+///
+/// `value` must encode to the same size on both passes. Any value does
+/// unless its `Serialize` implementation changes what it serializes from
+/// one call to the next, say through a `Cell`.
+pub unsafe fn encode_presized<T: Serialize>(
+    value: &T,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let size = encoded_size(value)?;
+    if size > buf.len() {
+        return Err(Error::OutOfSpace {
+            needed: size,
+            available: buf.len(),
+        });
+    }
+    let mut serializer =
+        ser::Serializer::with_writer(ser::Presized::new(&mut buf[..size]));
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_writer().len())
 }
 
 /// Decodes a value from provided buffer
@@ -62,11 +245,66 @@ pub fn decode<'de, T: Deserialize<'de>>(buf: &'de [u8]) -> Result<T, Error> {
     T::deserialize(&mut deserializer)
 }
 
+/// Decodes a value from provided buffer, accepting only the encoding that
+/// `encode` would produce
+///
+/// Every value has exactly one canonical encoding, so for signed payloads
+/// `encode(decode_canonical(buf))` is guaranteed to equal `buf`. On top of
+/// the checks `decode` already does, such as rejecting non-shortest UTF-8
+/// and flag bytes other than 0 or 1, trailing input is an error.
+pub fn decode_canonical<'de, T: Deserialize<'de>>(
+    buf: &'de [u8],
+) -> Result<T, Error> {
+    let mut deserializer = de::Deserializer::new(buf).canonical();
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+/// Decodes a value that fills `buf`, such as the payload of a frame,
+/// failing with `Error::TrailingBytes` otherwise
+///
+/// Unlike `decode_canonical`, evolving structs may hold more or fewer
+/// fields than the decoded type.
+pub(crate) fn decode_whole<'de, T: Deserialize<'de>>(
+    buf: &'de [u8],
+) -> Result<T, Error> {
+    let mut deserializer = de::Deserializer::new(buf);
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+/// Decodes a value from input split in two segments, such as the contents
+/// of a ring buffer
+///
+/// Borrowed values straddling the segments are copied to `scratch`, and
+/// fail with `Error::SegmentBoundary` if it is too small.
+pub fn decode_segmented<'de, T: Deserialize<'de>>(
+    first: &'de [u8],
+    second: &'de [u8],
+    scratch: &'de mut [u8],
+) -> Result<T, Error> {
+    let mut deserializer =
+        de::Deserializer::from_segments(first, second).with_scratch(scratch);
+    T::deserialize(&mut deserializer)
+}
+
+/// Decodes a value from provided buffer, enforcing resource limits
+pub fn decode_with_limits<'de, T: Deserialize<'de>>(
+    buf: &'de [u8],
+    limits: Limits,
+) -> Result<T, Error> {
+    let mut deserializer = de::Deserializer::with_limits(buf, limits);
+    T::deserialize(&mut deserializer)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use super::pathological;
+    use super::pathological::Evens;
 
     #[test]
     fn test_u8() {
@@ -150,14 +388,308 @@ mod tests {
         };
 
         let mut buf = [0u8; 7];
-        assert_eq!(encode(&orig, &mut buf), Err(Error::OutOfSpace))
+        assert_eq!(
+            encode(&orig, &mut buf),
+            Err(Error::OutOfSpace {
+                needed: 8,
+                available: 7
+            })
+        );
+
+        let mut buf = [0u8; 8];
+        assert_eq!(encode(&orig, &mut buf), Ok(8));
+    }
+
+    #[test]
+    fn test_presized() {
+        let orig: (u8, &str, Option<u64>, char) = (1, "abc", Some(7), '⚑');
+
+        assert_eq!(encoded_size(&orig), Ok(18));
+
+        let mut checked = [0u8; 18];
+        assert_eq!(encode(&orig, &mut checked), Ok(18));
+
+        let mut presized = [0u8; 32];
+        assert_eq!(unsafe { encode_presized(&orig, &mut presized) }, Ok(18));
+        assert_eq!(checked, presized[..18]);
+
+        let mut short = [0u8; 17];
+        assert_eq!(
+            unsafe { encode_presized(&orig, &mut short) },
+            Err(Error::OutOfSpace {
+                needed: 18,
+                available: 17
+            })
+        );
+        assert_eq!(short, [0u8; 17]);
+    }
+
+    #[test]
+    fn test_unknown_length_seq() {
+        let orig = (Evens(&[1u16, 2, 3, 4, 6]), 9u8);
+
+        let mut buf = [0u8; 9];
+        assert_eq!(encode(&orig, &mut buf), Ok(9));
+        assert_eq!(buf, [3, 0, 2, 0, 4, 0, 6, 0, 9]);
+
+        let decoded: (pathological::SeqLen, u8) = decode(&buf).unwrap();
+        assert_eq!(decoded, (pathological::SeqLen(3), 9));
+
+        assert_eq!(encoded_size(&orig), Ok(9));
+    }
+
+    #[test]
+    fn test_unknown_length_unpatchable() {
+        // A writer that can't go back, like a serial port
+        struct Stream(usize);
+
+        impl Writer for Stream {
+            fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+                self.0 += bytes.len();
+                Ok(())
+            }
+        }
+
+        let mut serializer = Serializer::with_writer(Stream(0));
+        assert_eq!(
+            Evens(&[2u16, 4]).serialize(&mut serializer),
+            Err(Error::UnknownLength)
+        );
+        assert_eq!(serializer.into_writer().0, 0);
+    }
+
+    #[test]
+    fn test_collect_str() {
+        struct Version(u8, u8, u16);
+
+        impl core::fmt::Display for Version {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                write!(f, "v{}.{}.{}", self.0, self.1, self.2)
+            }
+        }
+
+        impl Serialize for Version {
+            fn serialize<S: serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        let orig = (Version(1, 12, 300), 7u8);
+
+        let mut buf = [0u8; 12];
+        assert_eq!(encode(&orig, &mut buf), Ok(12));
+
+        let mut expected = [0u8; 12];
+        encode(&("v1.12.300", 7u8), &mut expected).unwrap();
+        assert_eq!(buf, expected);
+
+        let decoded: (&str, u8) = decode(&buf).unwrap();
+        assert_eq!(decoded, ("v1.12.300", 7));
+
+        let mut buf = [0u8; 11];
+        assert_eq!(
+            encode(&orig, &mut buf),
+            Err(Error::OutOfSpace {
+                needed: 12,
+                available: 11
+            })
+        );
     }
 
     #[test]
     fn test_insufficient_buffer_read() {
         let buf: [u8; 0] = Default::default();
 
-        assert_eq!(decode::<u16>(&buf), Err(Error::OutOfSpace))
+        assert_eq!(
+            decode::<u16>(&buf),
+            Err(Error::OutOfSpace {
+                needed: 2,
+                available: 0
+            })
+        )
+    }
+
+    #[test]
+    fn depth_limit() {
+        let orig = Some(Some(Some(3u8)));
+
+        let mut buf = [0u8; 4];
+        encode(&orig, &mut buf).unwrap();
+
+        let limits = Limits {
+            max_depth: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            decode_with_limits::<Option<Option<Option<u8>>>>(&buf, limits),
+            Err(Error::LimitExceeded)
+        );
+
+        let limits = Limits {
+            max_depth: 3,
+            ..Default::default()
+        };
+        assert_eq!(decode_with_limits(&buf, limits), Ok(orig));
+    }
+
+    #[test]
+    fn segmented() {
+        type Message<'a> = (u32, char, &'a str, Option<u128>, &'a str);
+        let orig: Message = (7, '⚑', "hello", Some(u128::MAX), "world");
+
+        let mut buf = [0u8; 64];
+        let len = encode(&orig, &mut buf).unwrap();
+
+        for seam in 0..=len {
+            let (first, second) = buf[..len].split_at(seam);
+            let mut scratch = [0u8; 5];
+            assert_eq!(
+                decode_segmented(first, second, &mut scratch),
+                Ok(orig),
+                "split at {}",
+                seam
+            );
+        }
+
+        // the bytes of "hello" start at 9
+        let (first, second) = buf[..len].split_at(11);
+        assert_eq!(
+            decode_segmented::<Message>(first, second, &mut []),
+            Err(Error::SegmentBoundary)
+        );
+        assert_eq!(
+            decode_segmented::<Message>(first, &second[..4], &mut [0; 5]),
+            Err(Error::OutOfSpace {
+                needed: 31,
+                available: 15
+            })
+        );
+    }
+
+    #[test]
+    fn element_limit() {
+        let orig: (&[u16], &[u16]) = (&[1, 2, 3], &[4, 5]);
+
+        let mut buf = [0u8; 14];
+        encode(&orig, &mut buf).unwrap();
+
+        let limits = Limits {
+            max_elements: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            decode_with_limits::<(pathological::SeqLen, pathological::SeqLen)>(
+                &buf, limits
+            ),
+            Err(Error::LimitExceeded)
+        );
+
+        let limits = Limits {
+            max_elements: 5,
+            ..Default::default()
+        };
+        assert_eq!(
+            decode_with_limits(&buf, limits),
+            Ok((pathological::SeqLen(3), pathological::SeqLen(2)))
+        );
+    }
+
+    #[test]
+    fn byte_limit() {
+        let orig: (u32, &str) = (7, "hello");
+
+        let mut buf = [0u8; 11];
+        encode(&orig, &mut buf).unwrap();
+
+        let limits = Limits {
+            max_bytes: 10,
+            ..Default::default()
+        };
+        assert_eq!(
+            decode_with_limits::<(u32, &str)>(&buf, limits),
+            Err(Error::LimitExceeded)
+        );
+
+        let limits = Limits {
+            max_bytes: 11,
+            ..Default::default()
+        };
+        assert_eq!(decode_with_limits(&buf, limits), Ok(orig));
+    }
+
+    #[test]
+    fn canonical() {
+        let mut buf = [0u8; 8];
+        let len = encode(&(true, Some('⚑')), &mut buf).unwrap();
+
+        assert_eq!(decode_canonical(&buf[..len]), Ok((true, Some('⚑'))));
+        assert_eq!(
+            decode_canonical::<(bool, Option<char>)>(&buf[..len + 1]),
+            Err(Error::TrailingBytes)
+        );
+
+        // flags other than 0 or 1
+        assert_eq!(
+            decode_canonical::<bool>(&[2]),
+            Err(Error::InvalidRepresentation)
+        );
+        assert_eq!(
+            decode_canonical::<Option<u8>>(&[2, 0]),
+            Err(Error::InvalidRepresentation)
+        );
+
+        // overlong 'A', and a UTF-16 surrogate
+        assert_eq!(
+            decode_canonical::<char>(&[0xc1, 0x81]),
+            Err(Error::InvalidRepresentation)
+        );
+        assert_eq!(
+            decode_canonical::<char>(&[0xed, 0xa0, 0x80]),
+            Err(Error::InvalidRepresentation)
+        );
+        assert_eq!(
+            decode_canonical::<&str>(&[2, 0, 0xc1, 0x81]),
+            Err(Error::InvalidRepresentation)
+        );
+    }
+
+    type Signed<'a> = (bool, Option<u16>, char, &'a str, [i8; 2]);
+
+    fn assert_canonical(bytes: &[u8]) {
+        if let Ok(value) = decode_canonical::<Signed>(bytes) {
+            let mut buf = [0u8; 64];
+            let len = encode(&value, &mut buf).unwrap();
+            assert_eq!(&buf[..len], bytes);
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn canonical_arbitrary(
+            bytes in proptest::collection::vec(proptest::num::u8::ANY, 0..24)
+        ) {
+            assert_canonical(&bytes);
+        }
+
+        #[test]
+        fn canonical_mutated(
+            b: bool,
+            o: Option<u16>,
+            c: char,
+            s in "\\PC{0,4}",
+            i: [i8; 2],
+            at: usize,
+            byte: u8,
+            append: bool,
+        ) {
+            let mut buf = [0u8; 64];
+            let len = encode(&(b, o, c, s.as_str(), i), &mut buf).unwrap();
+            buf[at % len] = byte;
+            assert_canonical(&buf[..len + append as usize]);
+        }
     }
 
     #[test]
@@ -170,6 +702,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::legacy_numeric_constants)]
     fn too_long_bytestring() {
         #[derive(PartialEq, Debug, Serialize, Deserialize)]
 
@@ -189,6 +722,53 @@ mod tests {
     }
 
     #[test]
+    fn invalid_length() {
+        // a sequence too short for the value, and a custom error whatever
+        // its message
+        #[derive(Debug, PartialEq)]
+        struct Triple;
+
+        impl<'de> Deserialize<'de> for Triple {
+            fn deserialize<D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<Self, D::Error> {
+                struct Visitor;
+
+                impl<'de> serde::de::Visitor<'de> for Visitor {
+                    type Value = Triple;
+
+                    fn expecting(
+                        &self,
+                        f: &mut core::fmt::Formatter,
+                    ) -> core::fmt::Result {
+                        f.write_str("three items")
+                    }
+
+                    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                        self,
+                        mut seq: A,
+                    ) -> Result<Triple, A::Error> {
+                        for i in 0..3 {
+                            seq.next_element::<u8>()?.ok_or_else(|| {
+                                serde::de::Error::invalid_length(i, &self)
+                            })?;
+                        }
+                        Err(serde::de::Error::custom(
+                            "length exceeds the bound",
+                        ))
+                    }
+                }
+
+                deserializer.deserialize_seq(Visitor)
+            }
+        }
+
+        assert_eq!(decode::<Triple>(&[2, 0, 1, 2]), Err(Error::Custom));
+        assert_eq!(decode::<Triple>(&[3, 0, 1, 2, 3]), Err(Error::Custom));
+    }
+
+    #[test]
+    #[allow(clippy::legacy_numeric_constants)]
     fn monster_struct() {
         #[derive(PartialEq, Debug, Serialize, Deserialize)]
         struct A {
@@ -279,9 +859,18 @@ mod tests {
         };
 
         let mut buf = [0u8; 256];
-        encode(&orig, &mut buf).unwrap();
+        let len = encode(&orig, &mut buf).unwrap();
 
         let decoded = decode(&buf).unwrap();
         assert_eq!(orig, decoded);
+
+        // zero-byte packing squeezes out the zeros of the wide integers
+        let mut packed = [0u8; 256];
+        let packed_len = pack_zeros(&buf[..len], &mut packed).unwrap();
+        assert_eq!((len, packed_len), (182, 104));
+
+        let mut unpacked = [0u8; 256];
+        let decoded = decode_zero_packed(&packed[..packed_len], &mut unpacked);
+        assert_eq!(decoded, Ok(orig));
     }
 }
@@ -0,0 +1,305 @@
+//! LZSS compression with a small fixed window, in the style of heatshrink.
+//!
+//! The compressed stream is a sequence of bit-packed tokens, least
+//! significant bit first: a `1` bit followed by a literal byte, or a `0` bit
+//! followed by an 8 bit offset and a 4 bit length, repeating 2 to 17 bytes
+//! from up to 256 bytes back. The last byte is padded with zero bits.
+//!
+//! Compressing needs no memory beyond the input and output, and
+//! decompressing reads its window back from the output.
+
+use serde::{Deserialize, Serialize};
+
+use crate::de::BitReader;
+use crate::ser::{write_to_slice, BitWriter, Writer};
+use crate::{decode, encode, Error};
+
+const WINDOW_BITS: u32 = 8;
+const LENGTH_BITS: u32 = 4;
+const WINDOW: usize = 1 << WINDOW_BITS;
+const MIN_MATCH: usize = 2;
+const MAX_MATCH: usize = MIN_MATCH + (1 << LENGTH_BITS) - 1;
+const LITERAL_BITS: usize = 9;
+
+/// Returns the offset and length of the longest match for the input at
+/// `pos`, preferring the nearest
+fn longest_match(input: &[u8], pos: usize) -> (usize, usize) {
+    let max = (input.len() - pos).min(MAX_MATCH);
+    let mut best = (0, 0);
+    for from in (pos.saturating_sub(WINDOW)..pos).rev() {
+        // matches may run on into the bytes they repeat
+        let len = (0..max)
+            .take_while(|i| input[from + i] == input[pos + i])
+            .count();
+        if len > best.1 {
+            best = (pos - from, len);
+            if len == max {
+                break;
+            }
+        }
+    }
+    best
+}
+
+fn compress_into<W: Writer + ?Sized>(
+    input: &[u8],
+    out: &mut W,
+) -> Result<(), Error> {
+    let mut out = BitWriter::new(out);
+    let mut pos = 0;
+    while pos < input.len() {
+        match longest_match(input, pos) {
+            (offset, len) if len >= MIN_MATCH => {
+                out.write_bits(0, 1)?;
+                out.write_bits((offset - 1) as u64, WINDOW_BITS)?;
+                out.write_bits((len - MIN_MATCH) as u64, LENGTH_BITS)?;
+                pos += len;
+            }
+            _ => {
+                out.write_bits(1, 1)?;
+                out.write_bits(input[pos] as u64, 8)?;
+                pos += 1;
+            }
+        }
+    }
+    out.finish()?;
+    Ok(())
+}
+
+/// Compresses `input` into `out`
+///
+/// Returns the number of bytes written. Input that doesn't compress grows by
+/// an eighth.
+///
+/// ```
+/// let line = b"temp=21 temp=21 temp=21 temp=21";
+/// let mut compressed = [0u8; 32];
+/// let len = fermion::compress(line, &mut compressed).unwrap();
+/// assert_eq!(len, 13);
+///
+/// let mut decompressed = [0u8; 32];
+/// let len = fermion::decompress(&compressed[..len], &mut decompressed);
+/// assert_eq!(len, Ok(line.len()));
+/// assert_eq!(&decompressed[..line.len()], line);
+/// ```
+pub fn compress(input: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    write_to_slice(|writer| compress_into(input, writer), out)
+}
+
+/// Reads `width` bits of a token, which the input must hold
+fn token_bits(input: &mut BitReader, width: u32) -> Result<usize, Error> {
+    match input.read_bits(width) {
+        Ok(bits) => Ok(bits as usize),
+        Err(_) => Err(Error::InvalidRepresentation),
+    }
+}
+
+/// Decompresses input compressed by `compress` into `out`
+///
+/// Returns the number of bytes written. Fails with
+/// `Error::InvalidRepresentation` on truncated input or references past the
+/// start of the output.
+pub fn decompress(input: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let mut input = BitReader::new(input);
+    // keeps counting past the end of `out`, to report the size needed
+    let mut len = 0;
+    while input.remaining() >= LITERAL_BITS {
+        if token_bits(&mut input, 1)? == 1 {
+            let byte = token_bits(&mut input, 8)? as u8;
+            if let Some(slot) = out.get_mut(len) {
+                *slot = byte;
+            }
+            len += 1;
+        } else {
+            let offset = token_bits(&mut input, WINDOW_BITS)? + 1;
+            let count = token_bits(&mut input, LENGTH_BITS)? + MIN_MATCH;
+            if offset > len {
+                return Err(Error::InvalidRepresentation);
+            }
+            for _ in 0..count {
+                if len < out.len() {
+                    out[len] = out[len - offset];
+                }
+                len += 1;
+            }
+        }
+    }
+    input.align()?;
+    if input.remaining() != 0 {
+        return Err(Error::InvalidRepresentation);
+    }
+    if len > out.len() {
+        return Err(Error::OutOfSpace {
+            needed: len,
+            available: out.len(),
+        });
+    }
+    Ok(len)
+}
+
+/// Encodes a value into `scratch`, and compresses it into `out`
+///
+/// Returns the number of bytes written to `out`.
+pub fn encode_compressed<T: Serialize>(
+    value: &T,
+    scratch: &mut [u8],
+    out: &mut [u8],
+) -> Result<usize, Error> {
+    let len = encode(value, scratch)?;
+    compress(&scratch[..len], out)
+}
+
+/// Decompresses a value compressed by `encode_compressed` into `buf`, and
+/// decodes it
+///
+/// Borrowed values point into `buf`.
+pub fn decode_compressed<'de, T: Deserialize<'de>>(
+    compressed: &[u8],
+    buf: &'de mut [u8],
+) -> Result<T, Error> {
+    let len = decompress(compressed, buf)?;
+    decode(&buf[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    type Record<'a> = (u32, u8, &'a str, Option<u16>);
+
+    fn round_trip(input: &[u8]) -> usize {
+        let mut compressed = [0u8; 4096];
+        let len = compress(input, &mut compressed).unwrap();
+        let mut decompressed = [0u8; 4096];
+        assert_eq!(
+            decompress(&compressed[..len], &mut decompressed),
+            Ok(input.len())
+        );
+        assert_eq!(&decompressed[..input.len()], input);
+        len
+    }
+
+    #[test]
+    fn runs() {
+        assert_eq!(round_trip(&[]), 0);
+        // a literal, then 6 references to it
+        assert_eq!(round_trip(&[b'a'; 100]), (9 + 6 * 13_usize).div_ceil(8));
+
+        // no repeats grow by a bit per byte
+        let mut distinct = [0u8; 256];
+        for (i, byte) in distinct.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        assert_eq!(round_trip(&distinct), 256 * 9 / 8);
+    }
+
+    #[test]
+    fn window() {
+        // 17 distinct bytes repeated 256 bytes later are found, 257 bytes
+        // later they are not
+        let mut near = [0u8; 273];
+        let mut far = [0u8; 274];
+        for i in 0..17 {
+            near[i] = i as u8 + 1;
+            far[i] = i as u8 + 1;
+        }
+        near.copy_within(..17, 256);
+        far.copy_within(..17, 257);
+
+        // literals, a zero and 14 references to it, then the repeat
+        assert_eq!(
+            round_trip(&near),
+            (17 * 9 + 9 + 14 * 13 + 13_usize).div_ceil(8)
+        );
+        assert_eq!(
+            round_trip(&far),
+            (17 * 9 + 9 + 14 * 13 + 9 + 17 * 9_usize).div_ceil(8)
+        );
+    }
+
+    #[test]
+    fn log_records() {
+        let mut scratch = [0u8; 1024];
+        let mut len = 0;
+        for i in 0..20 {
+            let record: Record = (1_700_000_000 + i, 2, "battery ok", None);
+            len += encode(&record, &mut scratch[len..]).unwrap();
+        }
+        let compressed = round_trip(&scratch[..len]);
+        assert!(compressed * 4 < len, "{} of {}", compressed, len);
+    }
+
+    #[test]
+    fn errors() {
+        let input = [b'x'; 64];
+        assert_eq!(
+            compress(&input, &mut [0u8; 2]),
+            Err(Error::OutOfSpace {
+                needed: (9 + 4 * 13_usize).div_ceil(8),
+                available: 2
+            })
+        );
+
+        let mut compressed = [0u8; 16];
+        let len = compress(&input, &mut compressed).unwrap();
+        assert_eq!(
+            decompress(&compressed[..len], &mut [0u8; 16]),
+            Err(Error::OutOfSpace {
+                needed: 64,
+                available: 16
+            })
+        );
+        // truncated, and with a byte of padding too many
+        assert_eq!(
+            decompress(&compressed[..len - 1], &mut [0u8; 64]),
+            Err(Error::InvalidRepresentation)
+        );
+        assert_eq!(
+            decompress(&compressed[..len + 1], &mut [0u8; 64]),
+            Err(Error::InvalidRepresentation)
+        );
+        // a reference before the start
+        assert_eq!(
+            decompress(&[0x00, 0x00], &mut [0u8; 64]),
+            Err(Error::InvalidRepresentation)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn payloads(
+            records in proptest::collection::vec(
+                (
+                    any::<u32>(),
+                    0u8..4,
+                    prop_oneof!["battery (ok|low)", "\\PC{0,16}"],
+                    any::<Option<u16>>(),
+                ),
+                0..24,
+            )
+        ) {
+            let mut scratch = [0u8; 2048];
+            let mut compressed = [0u8; 2560];
+            let mut buf = [0u8; 2048];
+            for (t, level, message, value) in &records {
+                let record: Record = (*t, *level, message, *value);
+                let len =
+                    encode_compressed(&record, &mut scratch, &mut compressed)
+                        .unwrap();
+                prop_assert_eq!(
+                    decode_compressed(&compressed[..len], &mut buf),
+                    Ok(record)
+                );
+            }
+
+            let mut len = 0;
+            for (t, level, message, value) in &records {
+                let record: Record = (*t, *level, message, *value);
+                len += encode(&record, &mut scratch[len..]).unwrap();
+            }
+            round_trip(&scratch[..len]);
+        }
+    }
+}
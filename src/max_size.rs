@@ -0,0 +1,71 @@
+/// Types with a finite upper bound on their encoded size.
+///
+/// Useful for sizing buffers at compile time:
+///
+/// ```
+/// use fermion::MaxSize;
+///
+/// let buf = [0u8; <(u32, Option<u16>)>::MAX_SIZE];
+/// assert_eq!(buf.len(), 7);
+/// ```
+pub trait MaxSize {
+    /// Maximum number of bytes the encoding of this type can occupy
+    const MAX_SIZE: usize;
+}
+
+macro_rules! impl_fixed {
+    ($($t:ty => $size:expr),*) => {
+        $(
+            impl MaxSize for $t {
+                const MAX_SIZE: usize = $size;
+            }
+        )*
+    };
+}
+
+impl_fixed! {
+    () => 0,
+    bool => 1,
+    char => 4,
+    u8 => 1,
+    u16 => 2,
+    u32 => 4,
+    u64 => 8,
+    u128 => 16,
+    usize => 8,
+    i8 => 1,
+    i16 => 2,
+    i32 => 4,
+    i64 => 8,
+    i128 => 16,
+    isize => 8
+}
+
+impl<T: MaxSize + ?Sized> MaxSize for &T {
+    const MAX_SIZE: usize = T::MAX_SIZE;
+}
+
+impl<T: MaxSize> MaxSize for Option<T> {
+    const MAX_SIZE: usize = 1 + T::MAX_SIZE;
+}
+
+impl<T: MaxSize, const N: usize> MaxSize for [T; N] {
+    const MAX_SIZE: usize = N * T::MAX_SIZE;
+}
+
+macro_rules! impl_tuple {
+    ($($t:ident)+) => {
+        impl<$($t: MaxSize),+> MaxSize for ($($t,)+) {
+            const MAX_SIZE: usize = 0 $(+ $t::MAX_SIZE)+;
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A B);
+impl_tuple!(A B C);
+impl_tuple!(A B C D);
+impl_tuple!(A B C D E);
+impl_tuple!(A B C D E F);
+impl_tuple!(A B C D E F G);
+impl_tuple!(A B C D E F G H);
@@ -0,0 +1,952 @@
+//! Order-preserving encoding, for keys of sorted key-value stores.
+//!
+//! Values of supported types compare bytewise the way they compare as
+//! values: `a < b` if and only if `encode_ordered(a) < encode_ordered(b)`.
+//! To get there the encoding differs from the default one:
+//!
+//! * integers are big-endian, with the sign bit of signed ones flipped
+//! * strings and byte slices have each zero byte escaped as `00 FF` and are
+//!   terminated by `00 00`
+//! * each sequence element is preceded by `01`, and the sequence is ended
+//!   by `00`
+//!
+//! Enum tags and option flags precede their payload as usual.
+
+use encode_unicode::{CharExt, Utf8Char};
+use serde::de::{DeserializeSeed, IntoDeserializer, Visitor};
+use serde::{Deserialize, Serialize};
+
+use crate::ser::{SizeCounter, SliceWriter, Writer};
+use crate::{too_short, Error};
+
+const ESCAPE: u8 = 0xff;
+const ELEMENT: u8 = 1;
+const END: u8 = 0;
+
+/// Writes the order-preserving encoding into a `Writer`
+pub struct OrderedSerializer<W> {
+    out: W,
+}
+
+impl<W: Writer> OrderedSerializer<W> {
+    /// Creates a serializer writing to `out`
+    pub fn with_writer(out: W) -> Self {
+        OrderedSerializer { out }
+    }
+
+    /// Returns the underlying writer
+    pub fn into_writer(self) -> W {
+        self.out
+    }
+
+    #[inline]
+    fn write_escaped(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        write_escaped(&mut self.out, bytes)?;
+        self.out.write(&[END, END])
+    }
+
+    #[inline]
+    fn write_tag(&mut self, variant_index: u32) -> Result<(), Error> {
+        if variant_index > 255 {
+            return Err(Error::TooManyVariants);
+        }
+        self.out.write(&[variant_index as u8])
+    }
+}
+
+fn write_escaped<W: Writer>(out: &mut W, bytes: &[u8]) -> Result<(), Error> {
+    for chunk in bytes.split_inclusive(|b| *b == 0) {
+        out.write(chunk)?;
+        if chunk.last() == Some(&0) {
+            out.write(&[ESCAPE])?;
+        }
+    }
+    Ok(())
+}
+
+struct EscapingWriter<'a, W> {
+    out: &'a mut W,
+    error: Option<Error>,
+}
+
+impl<W: Writer> core::fmt::Write for EscapingWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        write_escaped(self.out, s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            core::fmt::Error
+        })
+    }
+}
+
+macro_rules! serialize_int {
+    ($($f:ident: $t:ty => $flip:expr),*) => {
+        $(
+            fn $f(self, v: $t) -> Result<(), Error> {
+                self.out.write(&(v ^ $flip).to_be_bytes())
+            }
+        )*
+    };
+}
+
+impl<W: Writer> serde::ser::Serializer for &mut OrderedSerializer<W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = serde::ser::Impossible<(), Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    serialize_int! {
+        serialize_u8: u8 => 0,
+        serialize_u16: u16 => 0,
+        serialize_u32: u32 => 0,
+        serialize_u64: u64 => 0,
+        serialize_u128: u128 => 0,
+        serialize_i8: i8 => i8::MIN,
+        serialize_i16: i16 => i16::MIN,
+        serialize_i32: i32 => i32::MIN,
+        serialize_i64: i64 => i64::MIN,
+        serialize_i128: i128 => i128::MIN
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.out.write(&[v as u8])
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<(), Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<(), Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        // UTF-8 sorts by code point and is prefix-free
+        let (arr, sz) = v.to_utf8_array();
+        self.out.write(&arr[..sz])
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.write_escaped(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        self.write_escaped(v)
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.out.write(&[0])
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.out.write(&[1])?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), Error> {
+        self.write_tag(variant_index)
+    }
+
+    fn serialize_newtype_struct<T>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.write_tag(variant_index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        self.write_tag(variant_index)?;
+        Ok(self)
+    }
+
+    fn serialize_map(
+        self,
+        _len: Option<usize>,
+    ) -> Result<Self::SerializeMap, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        self.write_tag(variant_index)?;
+        Ok(self)
+    }
+
+    fn collect_str<T>(self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + core::fmt::Display,
+    {
+        let mut escaping = EscapingWriter {
+            out: &mut self.out,
+            error: None,
+        };
+        if core::fmt::write(&mut escaping, format_args!("{}", value)).is_err() {
+            return Err(escaping.error.unwrap_or(Error::Custom));
+        }
+        self.out.write(&[END, END])
+    }
+
+    #[inline]
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl<W: Writer> serde::ser::SerializeSeq for &mut OrderedSerializer<W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.out.write(&[ELEMENT])?;
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.out.write(&[END])
+    }
+}
+
+macro_rules! serialize_fields {
+    ($($t:ident),*) => {
+        $(
+            impl<W: Writer> serde::ser::$t for &mut OrderedSerializer<W> {
+                type Ok = ();
+                type Error = Error;
+
+                fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+                where
+                    T: ?Sized + Serialize,
+                {
+                    value.serialize(&mut **self)
+                }
+
+                fn end(self) -> Result<(), Error> {
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+serialize_fields!(SerializeTupleStruct, SerializeTupleVariant);
+
+impl<W: Writer> serde::ser::SerializeTuple for &mut OrderedSerializer<W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+macro_rules! serialize_named_fields {
+    ($($t:ident),*) => {
+        $(
+            impl<W: Writer> serde::ser::$t for &mut OrderedSerializer<W> {
+                type Ok = ();
+                type Error = Error;
+
+                fn serialize_field<T>(
+                    &mut self,
+                    _key: &'static str,
+                    value: &T,
+                ) -> Result<(), Error>
+                where
+                    T: ?Sized + Serialize,
+                {
+                    value.serialize(&mut **self)
+                }
+
+                fn end(self) -> Result<(), Error> {
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+serialize_named_fields!(SerializeStruct, SerializeStructVariant);
+
+/// Reads the order-preserving encoding from a byte buffer
+///
+/// Strings and byte slices are borrowed from the input. Those containing
+/// an escaped zero byte are unescaped into the scratch space provided with
+/// `with_scratch`. If it is too small they fail with `Error::OutOfSpace`,
+/// whose `needed` is the scratch space used up to and including them.
+/// Note that serde serializes a plain `&[u8]` as a sequence of `u8`, which
+/// sorts correctly but can't be decoded as borrowed bytes; use
+/// `BoundedBytes` for byte slices in keys.
+pub struct OrderedDeserializer<'de> {
+    buf: &'de [u8],
+    ofs: usize,
+    // Space for strings and byte slices with escaped zero bytes, and how
+    // much of it was taken
+    scratch: &'de mut [u8],
+    unescaped: usize,
+}
+
+impl<'de> OrderedDeserializer<'de> {
+    /// Creates a deserializer reading from the start of `buf`
+    pub fn new(buf: &'de [u8]) -> Self {
+        OrderedDeserializer {
+            buf,
+            ofs: 0,
+            scratch: &mut [],
+            unescaped: 0,
+        }
+    }
+
+    /// Provides space to unescape strings and byte slices holding zero
+    /// bytes to
+    pub fn with_scratch(mut self, scratch: &'de mut [u8]) -> Self {
+        self.scratch = scratch;
+        self
+    }
+
+    #[inline]
+    fn read<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let end = self.ofs + N;
+        if end > self.buf.len() {
+            return Err(Error::OutOfSpace {
+                needed: end,
+                available: self.buf.len(),
+            });
+        }
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(&self.buf[self.ofs..end]);
+        self.ofs = end;
+        Ok(bytes)
+    }
+
+    #[inline]
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read::<1>()?[0])
+    }
+
+    fn read_escaped(&mut self) -> Result<&'de [u8], Error> {
+        let start = self.ofs;
+        let mut escapes = 0;
+        loop {
+            let zero = self.buf[self.ofs..]
+                .iter()
+                .position(|b| *b == 0)
+                .ok_or(Error::OutOfSpace {
+                    needed: self.buf.len() + 2,
+                    available: self.buf.len(),
+                })?;
+            self.ofs += zero + 1;
+            match self.read_u8()? {
+                END => break,
+                ESCAPE => escapes += 1,
+                _ => return Err(Error::InvalidRepresentation),
+            }
+        }
+        let escaped = &self.buf[start..self.ofs - 2];
+        if escapes == 0 {
+            return Ok(escaped);
+        }
+        self.unescape(escaped, escapes)
+    }
+
+    /// Copies `escaped` to the scratch space without the `escapes` bytes
+    /// following its zero bytes
+    #[cold]
+    fn unescape(
+        &mut self,
+        escaped: &[u8],
+        escapes: usize,
+    ) -> Result<&'de [u8], Error> {
+        let len = escaped.len() - escapes;
+        if len > self.scratch.len() {
+            return Err(Error::OutOfSpace {
+                needed: self.unescaped + len,
+                available: self.unescaped + self.scratch.len(),
+            });
+        }
+        let (copy, rest) = core::mem::take(&mut self.scratch).split_at_mut(len);
+        self.scratch = rest;
+        self.unescaped += len;
+        let mut ofs = 0;
+        for (i, chunk) in escaped.split_inclusive(|b| *b == 0).enumerate() {
+            // each escape byte starts the chunk after its zero byte
+            let chunk = if i == 0 { chunk } else { &chunk[1..] };
+            copy[ofs..ofs + chunk.len()].copy_from_slice(chunk);
+            ofs += chunk.len();
+        }
+        Ok(copy)
+    }
+
+    /// Visits `len` elements of a tuple, or those of a sequence up to its
+    /// end marker
+    fn visit_seq<V: Visitor<'de>>(
+        &mut self,
+        visitor: V,
+        len: Option<usize>,
+    ) -> Result<V::Value, Error> {
+        let mut seq = SeqAccess {
+            deserializer: self,
+            len,
+            ended: false,
+        };
+        let value = visitor.visit_seq(&mut seq);
+        let ended = seq.ended;
+        too_short(value, ended)
+    }
+}
+
+macro_rules! deserialize_int {
+    ($($f:ident: $t:ty => $visit:ident, $flip:expr),*) => {
+        $(
+            fn $f<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(<$t>::from_be_bytes(self.read()?) ^ $flip)
+            }
+        )*
+    };
+}
+
+struct SeqAccess<'a, 'de> {
+    deserializer: &'a mut OrderedDeserializer<'de>,
+    len: Option<usize>,
+    // Whether the visitor was told the sequence ended
+    ended: bool,
+}
+
+impl<'de> serde::de::SeqAccess<'de> for SeqAccess<'_, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match &mut self.len {
+            // tuples
+            Some(0) => {
+                self.ended = true;
+                return Ok(None);
+            }
+            Some(len) => *len -= 1,
+            // sequences
+            None => match self.deserializer.read_u8()? {
+                ELEMENT => (),
+                END => {
+                    self.ended = true;
+                    return Ok(None);
+                }
+                _ => return Err(Error::InvalidRepresentation),
+            },
+        }
+        seed.deserialize(&mut *self.deserializer).map(Some)
+    }
+}
+
+impl<'de> serde::de::Deserializer<'de> for &mut OrderedDeserializer<'de> {
+    type Error = Error;
+
+    deserialize_int! {
+        deserialize_u8: u8 => visit_u8, 0,
+        deserialize_u16: u16 => visit_u16, 0,
+        deserialize_u32: u32 => visit_u32, 0,
+        deserialize_u64: u64 => visit_u64, 0,
+        deserialize_u128: u128 => visit_u128, 0,
+        deserialize_i8: i8 => visit_i8, i8::MIN,
+        deserialize_i16: i16 => visit_i16, i16::MIN,
+        deserialize_i32: i32 => visit_i32, i32::MIN,
+        deserialize_i64: i64 => visit_i64, i64::MIN,
+        deserialize_i128: i128 => visit_i128, i128::MIN
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.read_u8()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            _ => Err(Error::InvalidRepresentation),
+        }
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match Utf8Char::from_slice_start(&self.buf[self.ofs..]) {
+            Ok((c, count)) => {
+                self.ofs += count;
+                visitor.visit_char(c.to_char())
+            }
+            Err(_) => Err(Error::InvalidRepresentation),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match core::str::from_utf8(self.read_escaped()?) {
+            Ok(s) => visitor.visit_borrowed_str(s),
+            Err(_) => Err(Error::InvalidRepresentation),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(
+        self,
+        _: V,
+    ) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_bytes(self.read_escaped()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(
+        self,
+        _: V,
+    ) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.read_u8()? {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            _ => Err(Error::InvalidRepresentation),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.visit_seq(visitor, None)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.visit_seq(visitor, Some(len))
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(
+        self,
+        _: V,
+    ) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        _: V,
+    ) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    #[inline]
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl<'de> serde::de::EnumAccess<'de> for &mut OrderedDeserializer<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), Error> {
+        let tag = self.read_u8()? as u32;
+        let value = seed.deserialize(tag.into_deserializer())?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> serde::de::VariantAccess<'de> for &mut OrderedDeserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        serde::de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        serde::de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+/// Encodes a value into provided buffer, preserving its order
+///
+/// Returns the number of bytes written. On `Error::OutOfSpace`, `needed` is
+/// the full size of the encoded value.
+pub fn encode_ordered<T: Serialize>(
+    value: &T,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let mut serializer = OrderedSerializer::with_writer(SliceWriter::new(buf));
+    match value.serialize(&mut serializer) {
+        Ok(()) => Ok(serializer.into_writer().len()),
+        Err(Error::OutOfSpace { available, .. }) => {
+            let mut counter =
+                OrderedSerializer::with_writer(SizeCounter::default());
+            value.serialize(&mut counter)?;
+            Err(Error::OutOfSpace {
+                needed: counter.into_writer().size(),
+                available,
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Decodes a value encoded by `encode_ordered`
+///
+/// Strings and byte slices holding zero bytes fail with
+/// `Error::OutOfSpace`, use `decode_ordered_with_scratch` for those.
+pub fn decode_ordered<'de, T: Deserialize<'de>>(
+    buf: &'de [u8],
+) -> Result<T, Error> {
+    T::deserialize(&mut OrderedDeserializer::new(buf))
+}
+
+/// Decodes a value encoded by `encode_ordered`, unescaping strings and
+/// byte slices holding zero bytes into `scratch`
+///
+/// ```
+/// let mut buf = [0u8; 16];
+/// let len = fermion::encode_ordered(&(1u8, "a\0b"), &mut buf).unwrap();
+///
+/// let mut scratch = [0u8; 8];
+/// assert_eq!(
+///     fermion::decode_ordered_with_scratch(&buf[..len], &mut scratch),
+///     Ok((1u8, "a\0b"))
+/// );
+/// ```
+pub fn decode_ordered_with_scratch<'de, T: Deserialize<'de>>(
+    buf: &'de [u8],
+    scratch: &'de mut [u8],
+) -> Result<T, Error> {
+    T::deserialize(&mut OrderedDeserializer::new(buf).with_scratch(scratch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BoundedBytes;
+
+    use proptest::prelude::*;
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+    enum Kind<'a> {
+        Counter(u64),
+        Label(&'a str),
+        Pair { a: i8, b: bool },
+    }
+
+    type Key<'a> = (
+        i32,
+        &'a str,
+        Option<u16>,
+        BoundedBytes<'a, 8>,
+        char,
+        Kind<'a>,
+    );
+
+    fn encoded(key: &Key) -> ([u8; 128], usize) {
+        let mut buf = [0u8; 128];
+        let len = encode_ordered(key, &mut buf).unwrap();
+        (buf, len)
+    }
+
+    fn key_strategy() -> impl Strategy<
+        Value = (i32, String, Option<u16>, Vec<u8>, char, u8, u64, i8, bool),
+    > {
+        (
+            any::<i32>(),
+            "[a\\x00b]{0,4}",
+            any::<Option<u16>>(),
+            proptest::collection::vec(0u8..3, 0..4),
+            any::<char>(),
+            0u8..3,
+            any::<u64>(),
+            any::<i8>(),
+            any::<bool>(),
+        )
+    }
+
+    fn bytes(b: &[u8]) -> BoundedBytes<'_, 8> {
+        BoundedBytes::new(b).unwrap()
+    }
+
+    fn kind(tag: u8, n: u64, s: &str, a: i8, b: bool) -> Kind<'_> {
+        match tag {
+            0 => Kind::Counter(n),
+            1 => Kind::Label(s),
+            _ => Kind::Pair { a, b },
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn order_preserved(x in key_strategy(), y in key_strategy()) {
+            let a: Key = (x.0, &x.1, x.2, bytes(&x.3), x.4, kind(x.5, x.6, &x.1, x.7, x.8));
+            let b: Key = (y.0, &y.1, y.2, bytes(&y.3), y.4, kind(y.5, y.6, &y.1, y.7, y.8));
+
+            let (ea, la) = encoded(&a);
+            let (eb, lb) = encoded(&b);
+            prop_assert_eq!(a.cmp(&b), ea[..la].cmp(&eb[..lb]));
+        }
+
+        #[test]
+        fn sequences(a: Vec<i16>, b: Vec<i16>) {
+            let mut ea = [0u8; 512];
+            let mut eb = [0u8; 512];
+            let la = encode_ordered(&a.as_slice(), &mut ea).unwrap();
+            let lb = encode_ordered(&b.as_slice(), &mut eb).unwrap();
+            prop_assert_eq!(a.cmp(&b), ea[..la].cmp(&eb[..lb]));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let key: Key =
+            (-3, "abc", Some(7), bytes(&[1, 2]), '⚑', Kind::Label("x"));
+        let (buf, len) = encoded(&key);
+
+        assert_eq!(decode_ordered(&buf[..len]), Ok(key));
+    }
+
+    #[test]
+    fn escaped_zero() {
+        let mut buf = [0u8; 16];
+        let len = encode_ordered(&"a\0b", &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[b'a', 0, 0xff, b'b', 0, 0]);
+
+        assert_eq!(
+            decode_ordered::<&str>(&buf[..len]),
+            Err(Error::OutOfSpace {
+                needed: 3,
+                available: 0
+            })
+        );
+        assert_eq!(
+            decode_ordered_with_scratch::<&str>(&buf[..len], &mut [0u8; 2]),
+            Err(Error::OutOfSpace {
+                needed: 3,
+                available: 2
+            })
+        );
+        assert_eq!(
+            decode_ordered_with_scratch(&buf[..len], &mut [0u8; 3]),
+            Ok("a\0b")
+        );
+
+        // zero bytes at either end, next to each other, and in a later
+        // field sharing the scratch space
+        let key = (bytes(&[0, 0, 7, 0]), "\0", 5u8);
+        let len = encode_ordered(&key, &mut buf).unwrap();
+        assert_eq!(
+            decode_ordered_with_scratch(&buf[..len], &mut [0u8; 5]),
+            Ok(key)
+        );
+        assert_eq!(
+            decode_ordered_with_scratch::<(BoundedBytes<8>, &str, u8)>(
+                &buf[..len],
+                &mut [0u8; 4]
+            ),
+            Err(Error::OutOfSpace {
+                needed: 5,
+                available: 4
+            })
+        );
+    }
+
+    #[test]
+    fn out_of_space() {
+        let key: Key =
+            (-3, "abc", Some(7), bytes(&[1, 2]), '⚑', Kind::Label("x"));
+        let (_, len) = encoded(&key);
+        assert_eq!(
+            encode_ordered(&key, &mut [0u8; 6]),
+            Err(Error::OutOfSpace {
+                needed: len,
+                available: 6
+            })
+        );
+    }
+}
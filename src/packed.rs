@@ -0,0 +1,1461 @@
+//! Bit-packed encoding, for payloads where every byte counts.
+//!
+//! Values are packed into a stream of bits, least significant bit first,
+//! instead of whole bytes:
+//!
+//! * `bool`s and option flags take a single bit
+//! * integers take their full width, or the width given by `Bits`
+//! * enum tags take 1 bit for the first variant, 3 bits for the next two, 5
+//!   for the four after and so on, as the number of variants isn't known
+//!   when serializing
+//! * `char`s take 21 bits, and sequence lengths 16, or 8 after a bounded
+//!   prefix
+//! * strings and byte slices start at the next byte boundary, padding with
+//!   zero bits, and are encoded as usual from there
+//!
+//! The lengths of sequences of unknown length and of `collect_str` are
+//! patched in once their elements are written, at whatever bit they start.
+//! That takes a writer that can patch anywhere, such as a slice, and fails
+//! with `Error::UnknownLength` otherwise, or may with more than four of
+//! those sequences nested in each other. `Evolving` structs start at the
+//! next byte boundary, after their size in bits.
+
+use core::convert::TryFrom;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+
+use serde::de::{DeserializeSeed, IntoDeserializer, Visitor};
+use serde::{Deserialize, Serialize};
+
+use crate::bounded::SHORT_PREFIX;
+use crate::de::BitReader;
+use crate::evolving::{EvolvingStruct, ReadStruct, EVOLVING};
+use crate::max_size::MaxSize;
+use crate::ser::{BitWriter, SizeCounter, SliceWriter, Writer};
+use crate::{length_exceeded, too_short, Error};
+
+/// Prefix of the newtype names giving the bit width of the next integer.
+/// Other formats, including the byte encoding, just see a newtype struct.
+const BITS: &str = "$fermion::Bits";
+
+macro_rules! width_names {
+    ($($n:literal)*) => {
+        [$(concat!("$fermion::Bits", $n)),*]
+    };
+}
+
+const WIDTH_NAMES: [&str; 64] = width_names!(
+    1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27
+    28 29 30 31 32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47 48 49 50 51
+    52 53 54 55 56 57 58 59 60 61 62 63 64
+);
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Integers that `Bits` can narrow
+pub trait PackedInt: sealed::Sealed + Copy {
+    /// Full width of the integer
+    const BITS: u32;
+
+    #[doc(hidden)]
+    fn fits(self, width: u32) -> bool;
+}
+
+macro_rules! packed_int {
+    ($($t:ty => |$v:ident, $width:ident| $fits:expr),*) => {
+        $(
+            impl sealed::Sealed for $t {}
+
+            impl PackedInt for $t {
+                const BITS: u32 = <$t>::BITS;
+
+                fn fits(self, width: u32) -> bool {
+                    let ($v, $width) = (self, width);
+                    width >= Self::BITS || $fits
+                }
+            }
+        )*
+    };
+}
+
+// Signed integers fit if shifting out all but the sign bit leaves 0 or -1
+packed_int! {
+    u8 => |v, width| v >> width == 0,
+    u16 => |v, width| v >> width == 0,
+    u32 => |v, width| v >> width == 0,
+    u64 => |v, width| v >> width == 0,
+    i8 => |v, width| matches!(v >> (width - 1), 0 | -1),
+    i16 => |v, width| matches!(v >> (width - 1), 0 | -1),
+    i32 => |v, width| matches!(v >> (width - 1), 0 | -1),
+    i64 => |v, width| matches!(v >> (width - 1), 0 | -1)
+}
+
+/// An integer encoded in `N` bits by the packed encoding
+///
+/// Signed integers are stored in two's complement. Values that don't fit are
+/// rejected with `Error::LengthExceeded` both when constructing the value
+/// and when decoding it. The byte encoding ignores the width.
+///
+/// ```
+/// use fermion::Bits;
+///
+/// let reading = (Bits::<u16, 10>::new(1000).unwrap(), true);
+/// let mut buf = [0u8; 2];
+/// assert_eq!(fermion::encode_packed(&reading, &mut buf), Ok(2));
+/// assert_eq!(fermion::decode_packed(&buf), Ok(reading));
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Bits<T, const N: u32>(T);
+
+impl<T: PackedInt, const N: u32> Bits<T, N> {
+    const NAME: &'static str = {
+        assert!(N >= 1 && N <= T::BITS, "bit width out of range");
+        WIDTH_NAMES[N as usize - 1]
+    };
+
+    /// Wraps `value`, failing if it doesn't fit in `N` bits
+    pub fn new(value: T) -> Result<Self, Error> {
+        let _ = Self::NAME;
+        if value.fits(N) {
+            Ok(Bits(value))
+        } else {
+            Err(Error::LengthExceeded)
+        }
+    }
+
+    /// Returns the wrapped integer
+    pub fn get(&self) -> T {
+        self.0
+    }
+}
+
+impl<T, const N: u32> Deref for Bits<T, N> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: MaxSize, const N: u32> MaxSize for Bits<T, N> {
+    const MAX_SIZE: usize = T::MAX_SIZE;
+}
+
+impl<T: PackedInt + Serialize, const N: u32> Serialize for Bits<T, N> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(Self::NAME, &self.0)
+    }
+}
+
+struct BitsVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for BitsVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a narrow integer")
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        T::deserialize(deserializer)
+    }
+}
+
+impl<'de, T, const N: u32> Deserialize<'de> for Bits<T, N>
+where
+    T: PackedInt + Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value: T = deserializer
+            .deserialize_newtype_struct(Self::NAME, BitsVisitor(PhantomData))?;
+        if value.fits(N) {
+            Ok(Bits(value))
+        } else {
+            Err(length_exceeded(N as usize))
+        }
+    }
+}
+
+/// Parses the width out of a `Bits` newtype name
+fn width_of(name: &str) -> Option<u32> {
+    let width = name.strip_prefix(BITS)?.parse().ok()?;
+    if (1..=64).contains(&width) {
+        Some(width)
+    } else {
+        None
+    }
+}
+
+/// Checks that a size in bits fits the 16 bits of an `Evolving` header
+fn bits_len(bits: usize) -> Result<u16, Error> {
+    u16::try_from(bits).map_err(|_| Error::LengthExceeded)
+}
+
+/// Writes the packed encoding into a `Writer`
+pub struct PackedSerializer<W> {
+    out: BitWriter<W>,
+    // Set by a `SHORT_PREFIX` newtype, consumed by the next length prefix
+    short_prefix: bool,
+    // Set by a `Bits` newtype, consumed by the next integer
+    width: Option<u32>,
+}
+
+/// The bits of a length prefix waiting to be patched
+#[derive(Clone, Copy)]
+struct Reserved {
+    at: usize,
+    width: u32,
+}
+
+/// Writes the elements of a sequence, patching in its length at the end if
+/// it wasn't known up front
+pub struct PackedSeqSerializer<'a, W> {
+    serializer: &'a mut PackedSerializer<W>,
+    unknown: Option<(Reserved, usize)>,
+}
+
+impl<W: Writer> PackedSerializer<W> {
+    /// Creates a serializer writing to `out`
+    pub fn with_writer(out: W) -> Self {
+        PackedSerializer {
+            out: BitWriter::new(out),
+            short_prefix: false,
+            width: None,
+        }
+    }
+
+    /// Pads the last byte with zero bits and returns the underlying writer
+    pub fn finish(self) -> Result<W, Error> {
+        self.out.finish()
+    }
+
+    fn write_unsigned(&mut self, v: u64, bits: u32) -> Result<(), Error> {
+        let width = self.width.take().map_or(bits, |w| w.min(bits));
+        if width < 64 && v >> width != 0 {
+            return Err(Error::LengthExceeded);
+        }
+        self.out.write_bits(v, width)
+    }
+
+    fn write_signed(&mut self, v: i64, bits: u32) -> Result<(), Error> {
+        let width = self.width.take().map_or(bits, |w| w.min(bits));
+        let rest = v >> (width - 1);
+        if width < 64 && rest != 0 && rest != -1 {
+            return Err(Error::LengthExceeded);
+        }
+        self.out.write_bits(v as u64, width)
+    }
+
+    fn write_u128(&mut self, v: u128) -> Result<(), Error> {
+        self.width = None;
+        self.out.write_bits(v as u64, 64)?;
+        self.out.write_bits((v >> 64) as u64, 64)
+    }
+
+    fn write_len(&mut self, len: usize) -> Result<(), Error> {
+        let short = core::mem::replace(&mut self.short_prefix, false);
+        let width = if short { 8 } else { 16 };
+        if len >> width != 0 {
+            return Err(Error::LengthExceeded);
+        }
+        self.out.write_bits(len as u64, width)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.out.align()?;
+        self.write_len(bytes.len())?;
+        self.out.write_bytes(bytes)
+    }
+
+    /// Reserves the bits of a length prefix, to be filled in by `patch_len`
+    fn reserve_len(&mut self) -> Result<Reserved, Error> {
+        let short = core::mem::replace(&mut self.short_prefix, false);
+        let width = if short { 8 } else { 16 };
+        let at = self.out.reserve_bits(width)?;
+        Ok(Reserved { at, width })
+    }
+
+    fn patch_len(
+        &mut self,
+        reserved: Reserved,
+        len: usize,
+    ) -> Result<(), Error> {
+        if len >> reserved.width != 0 {
+            return Err(Error::LengthExceeded);
+        }
+        self.out.patch_bits(reserved.at, len as u64, reserved.width)
+    }
+
+    /// Writes a value after its size in bits, patched in if the writer can
+    fn write_evolving<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        if self.out.position().is_some() {
+            let at = self.out.reserve(2)?;
+            let start = (at + 2) * 8;
+            value.serialize(&mut *self)?;
+            let end = self.out.position().ok_or(Error::UnknownLength)?;
+            let bits = bits_len(end - start)?;
+            return self.out.patch(at, &bits.to_le_bytes());
+        }
+        // sized from a byte boundary, like the value itself
+        let mut counter = PackedSerializer::with_writer(SizeCounter::default());
+        value.serialize(&mut counter)?;
+        let bits = bits_len(counter.out.position().unwrap_or(0))?;
+        self.out.align()?;
+        self.out.write_bits(bits as u64, 16)?;
+        value.serialize(self)
+    }
+
+    fn write_tag(&mut self, variant_index: u32) -> Result<(), Error> {
+        if variant_index > 255 {
+            return Err(Error::TooManyVariants);
+        }
+        // `k` zero bits and a one, then the `k` bits below the top one of
+        // `variant_index + 1`
+        let x = variant_index + 1;
+        let k = 31 - x.leading_zeros();
+        self.out.write_bits(1 << k, k + 1)?;
+        self.out.write_bits((x & ((1 << k) - 1)) as u64, k)
+    }
+}
+
+macro_rules! serialize_int {
+    ($($f:ident: $t:ty => $write:ident as $wide:ty),*) => {
+        $(
+            fn $f(self, v: $t) -> Result<(), Error> {
+                self.$write(v as $wide, <$t>::BITS)
+            }
+        )*
+    };
+}
+
+impl<'a, W: Writer> serde::ser::Serializer for &'a mut PackedSerializer<W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = PackedSeqSerializer<'a, W>;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = serde::ser::Impossible<(), Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    serialize_int! {
+        serialize_u8: u8 => write_unsigned as u64,
+        serialize_u16: u16 => write_unsigned as u64,
+        serialize_u32: u32 => write_unsigned as u64,
+        serialize_u64: u64 => write_unsigned as u64,
+        serialize_i8: i8 => write_signed as i64,
+        serialize_i16: i16 => write_signed as i64,
+        serialize_i32: i32 => write_signed as i64,
+        serialize_i64: i64 => write_signed as i64
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), Error> {
+        self.write_u128(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), Error> {
+        self.write_u128(v as u128)
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.out.write_bits(v as u64, 1)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<(), Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<(), Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.out.write_bits(v as u64, 21)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.write_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        self.write_bytes(v)
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.out.write_bits(0, 1)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.out.write_bits(1, 1)?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), Error> {
+        self.write_tag(variant_index)
+    }
+
+    fn serialize_newtype_struct<T>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        if name == SHORT_PREFIX {
+            self.short_prefix = true;
+        } else if name == EVOLVING {
+            return self.write_evolving(value);
+        } else if let Some(width) = width_of(name) {
+            self.width = Some(width);
+        }
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.write_tag(variant_index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(
+        self,
+        len: Option<usize>,
+    ) -> Result<PackedSeqSerializer<'a, W>, Error> {
+        let unknown = match len {
+            Some(len) => {
+                self.write_len(len)?;
+                None
+            }
+            None => Some((self.reserve_len()?, 0)),
+        };
+        Ok(PackedSeqSerializer {
+            serializer: self,
+            unknown,
+        })
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        self.write_tag(variant_index)?;
+        Ok(self)
+    }
+
+    fn serialize_map(
+        self,
+        _len: Option<usize>,
+    ) -> Result<Self::SerializeMap, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        self.write_tag(variant_index)?;
+        Ok(self)
+    }
+
+    fn collect_str<T>(self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + fmt::Display,
+    {
+        struct Text<'a, W> {
+            out: &'a mut BitWriter<W>,
+            len: usize,
+            error: Option<Error>,
+        }
+
+        impl<W: Writer> fmt::Write for Text<'_, W> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                match self.out.write_bytes(s.as_bytes()) {
+                    Ok(()) => {
+                        self.len += s.len();
+                        Ok(())
+                    }
+                    Err(e) => {
+                        self.error = Some(e);
+                        Err(fmt::Error)
+                    }
+                }
+            }
+        }
+
+        self.out.align()?;
+        let reserved = self.reserve_len()?;
+        let mut text = Text {
+            out: &mut self.out,
+            len: 0,
+            error: None,
+        };
+        if fmt::write(&mut text, format_args!("{}", value)).is_err() {
+            return Err(text.error.unwrap_or(Error::Custom));
+        }
+        let len = text.len;
+        self.patch_len(reserved, len)
+    }
+
+    #[inline]
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+macro_rules! serialize_elements {
+    ($($t:ident),*) => {
+        $(
+            impl<W: Writer> serde::ser::$t for &mut PackedSerializer<W> {
+                type Ok = ();
+                type Error = Error;
+
+                fn serialize_element<T>(
+                    &mut self,
+                    value: &T,
+                ) -> Result<(), Error>
+                where
+                    T: ?Sized + Serialize,
+                {
+                    value.serialize(&mut **self)
+                }
+
+                fn end(self) -> Result<(), Error> {
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+serialize_elements!(SerializeTuple);
+
+impl<W: Writer> serde::ser::SerializeSeq for PackedSeqSerializer<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        if let Some((_, len)) = &mut self.unknown {
+            *len += 1;
+        }
+        value.serialize(&mut *self.serializer)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        match self.unknown {
+            Some((reserved, len)) => self.serializer.patch_len(reserved, len),
+            None => Ok(()),
+        }
+    }
+}
+
+macro_rules! serialize_fields {
+    ($($t:ident),*) => {
+        $(
+            impl<W: Writer> serde::ser::$t for &mut PackedSerializer<W> {
+                type Ok = ();
+                type Error = Error;
+
+                fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+                where
+                    T: ?Sized + Serialize,
+                {
+                    value.serialize(&mut **self)
+                }
+
+                fn end(self) -> Result<(), Error> {
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+serialize_fields!(SerializeTupleStruct, SerializeTupleVariant);
+
+macro_rules! serialize_named_fields {
+    ($($t:ident),*) => {
+        $(
+            impl<W: Writer> serde::ser::$t for &mut PackedSerializer<W> {
+                type Ok = ();
+                type Error = Error;
+
+                fn serialize_field<T>(
+                    &mut self,
+                    _key: &'static str,
+                    value: &T,
+                ) -> Result<(), Error>
+                where
+                    T: ?Sized + Serialize,
+                {
+                    value.serialize(&mut **self)
+                }
+
+                fn end(self) -> Result<(), Error> {
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+serialize_named_fields!(SerializeStruct, SerializeStructVariant);
+
+/// Reads the packed encoding from a byte buffer
+///
+/// Padding bits before strings and byte slices must be zero.
+pub struct PackedDeserializer<'de> {
+    input: BitReader<'de>,
+    // Set by a `SHORT_PREFIX` newtype, consumed by the next length prefix
+    short_prefix: bool,
+    // Set by a `Bits` newtype, consumed by the next integer
+    width: Option<u32>,
+}
+
+impl<'de> PackedDeserializer<'de> {
+    /// Creates a deserializer reading from the start of `buf`
+    pub fn new(buf: &'de [u8]) -> Self {
+        PackedDeserializer {
+            input: BitReader::new(buf),
+            short_prefix: false,
+            width: None,
+        }
+    }
+
+    /// Number of bytes consumed so far, counting a partial one
+    pub fn position(&self) -> usize {
+        self.input.position()
+    }
+
+    fn read_unsigned(&mut self, bits: u32) -> Result<u64, Error> {
+        let width = self.width.take().map_or(bits, |w| w.min(bits));
+        self.input.read_bits(width)
+    }
+
+    fn read_signed(&mut self, bits: u32) -> Result<i64, Error> {
+        let width = self.width.take().map_or(bits, |w| w.min(bits));
+        let shift = 64 - width;
+        Ok((self.input.read_bits(width)? << shift) as i64 >> shift)
+    }
+
+    fn read_u128(&mut self) -> Result<u128, Error> {
+        self.width = None;
+        let low = self.input.read_bits(64)? as u128;
+        Ok(low | (self.input.read_bits(64)? as u128) << 64)
+    }
+
+    fn read_flag(&mut self) -> Result<bool, Error> {
+        Ok(self.input.read_bits(1)? == 1)
+    }
+
+    fn read_len(&mut self) -> Result<usize, Error> {
+        let short = core::mem::replace(&mut self.short_prefix, false);
+        Ok(self.input.read_bits(if short { 8 } else { 16 })? as usize)
+    }
+
+    fn read_bytes(&mut self) -> Result<&'de [u8], Error> {
+        self.input.align()?;
+        let len = self.read_len()?;
+        self.input.read_slice(len)
+    }
+
+    /// Reads a value after its size in bits, skipping what it leaves
+    /// unread
+    fn read_evolving<V: Visitor<'de>>(
+        &mut self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.input.align()?;
+        let bits = self.input.read_bits(16)? as usize;
+        let end = self.input.bit_position() + bits;
+        let value = visitor.visit_newtype_struct(EvolvingStruct {
+            deserializer: &mut *self,
+            end,
+        })?;
+        if self.input.bit_position() > end {
+            return Err(Error::InvalidRepresentation);
+        }
+        self.input.skip_to(end)?;
+        Ok(value)
+    }
+
+    /// Visits `len` elements, or those before bit `end` of an evolving
+    /// struct
+    fn visit_seq<V: Visitor<'de>>(
+        &mut self,
+        visitor: V,
+        len: usize,
+        end: Option<usize>,
+    ) -> Result<V::Value, Error> {
+        let mut seq = SeqAccess {
+            deserializer: self,
+            len,
+            end,
+            ended: false,
+        };
+        let value = visitor.visit_seq(&mut seq);
+        let ended = seq.ended;
+        too_short(value, ended)
+    }
+
+    fn read_tag(&mut self) -> Result<u32, Error> {
+        let mut k = 0;
+        while !self.read_flag()? {
+            k += 1;
+            if k > 8 {
+                return Err(Error::InvalidRepresentation);
+            }
+        }
+        let x = (1 << k) | self.input.read_bits(k)? as u32;
+        if x > 256 {
+            return Err(Error::InvalidRepresentation);
+        }
+        Ok(x - 1)
+    }
+}
+
+macro_rules! deserialize_int {
+    ($($f:ident: $t:ty => $visit:ident, $read:ident),*) => {
+        $(
+            fn $f<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.$read(<$t>::BITS)? as $t)
+            }
+        )*
+    };
+}
+
+struct SeqAccess<'a, 'de> {
+    deserializer: &'a mut PackedDeserializer<'de>,
+    len: usize,
+    // The fields of an evolving struct run out at this bit, if not before
+    end: Option<usize>,
+    // Whether the visitor was told the sequence ended
+    ended: bool,
+}
+
+impl<'de> serde::de::SeqAccess<'de> for SeqAccess<'_, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.len == 0 {
+            self.ended = true;
+            return Ok(None);
+        }
+        if let Some(end) = self.end {
+            // fields added after the input was encoded
+            if self.deserializer.input.bit_position() >= end {
+                self.ended = true;
+                return Ok(None);
+            }
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.deserializer).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'de> ReadStruct<'de> for PackedDeserializer<'de> {
+    fn read_struct<V: Visitor<'de>>(
+        &mut self,
+        fields: &'static [&'static str],
+        visitor: V,
+        end: usize,
+    ) -> Result<V::Value, Error> {
+        self.visit_seq(visitor, fields.len(), Some(end))
+    }
+}
+
+impl<'de> serde::de::Deserializer<'de> for &mut PackedDeserializer<'de> {
+    type Error = Error;
+
+    deserialize_int! {
+        deserialize_u8: u8 => visit_u8, read_unsigned,
+        deserialize_u16: u16 => visit_u16, read_unsigned,
+        deserialize_u32: u32 => visit_u32, read_unsigned,
+        deserialize_u64: u64 => visit_u64, read_unsigned,
+        deserialize_i8: i8 => visit_i8, read_signed,
+        deserialize_i16: i16 => visit_i16, read_signed,
+        deserialize_i32: i32 => visit_i32, read_signed,
+        deserialize_i64: i64 => visit_i64, read_signed
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_u128(self.read_u128()?)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_i128(self.read_u128()? as i128)
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_bool(self.read_flag()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match char::from_u32(self.input.read_bits(21)? as u32) {
+            Some(c) => visitor.visit_char(c),
+            None => Err(Error::InvalidRepresentation),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match core::str::from_utf8(self.read_bytes()?) {
+            Ok(s) => visitor.visit_borrowed_str(s),
+            Err(_) => Err(Error::InvalidRepresentation),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_bytes(self.read_bytes()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        if self.read_flag()? {
+            visitor.visit_some(self)
+        } else {
+            visitor.visit_none()
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        if name == SHORT_PREFIX {
+            self.short_prefix = true;
+        } else if name == EVOLVING {
+            return self.read_evolving(visitor);
+        } else if let Some(width) = width_of(name) {
+            self.width = Some(width);
+        }
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let len = self.read_len()?;
+        self.visit_seq(visitor, len, None)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.visit_seq(visitor, len, None)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(
+        self,
+        _: V,
+    ) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        _: V,
+    ) -> Result<V::Value, Error> {
+        Err(Error::NotSupported)
+    }
+
+    #[inline]
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl<'de> serde::de::EnumAccess<'de> for &mut PackedDeserializer<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), Error> {
+        let tag = self.read_tag()?;
+        let value = seed.deserialize(tag.into_deserializer())?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> serde::de::VariantAccess<'de> for &mut PackedDeserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        serde::de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        serde::de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+/// Returns the number of bytes `value` packs to
+pub fn packed_size<T: Serialize>(value: &T) -> Result<usize, Error> {
+    let mut serializer = PackedSerializer::with_writer(SizeCounter::default());
+    value.serialize(&mut serializer)?;
+    Ok(serializer.finish()?.size())
+}
+
+/// Encodes a value into provided buffer, packing it into bits
+///
+/// Returns the number of bytes written. On `Error::OutOfSpace`, `needed` is
+/// the full size of the packed value.
+pub fn encode_packed<T: Serialize>(
+    value: &T,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let mut serializer = PackedSerializer::with_writer(SliceWriter::new(buf));
+    let result = match value.serialize(&mut serializer) {
+        Ok(()) => serializer.finish().map(|writer| writer.len()),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(Error::OutOfSpace { available, .. }) => Err(Error::OutOfSpace {
+            needed: packed_size(value)?,
+            available,
+        }),
+        result => result,
+    }
+}
+
+/// Decodes a value encoded by `encode_packed`
+pub fn decode_packed<'de, T: Deserialize<'de>>(
+    buf: &'de [u8],
+) -> Result<T, Error> {
+    T::deserialize(&mut PackedDeserializer::new(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathological::{Evens, Unsized, DIGITS};
+    use crate::{decode, encode, encoded_size, Evolving};
+
+    use proptest::prelude::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Mode {
+        Idle,
+        Sampling(Bits<u8, 4>),
+        Alarm { level: Bits<i8, 3> },
+    }
+
+    /// A sensor report for a 32 byte LoRa payload
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Report<'a> {
+        flags: [bool; 12],
+        channels: [Option<Bits<u16, 12>>; 6],
+        temperature: Bits<i16, 11>,
+        mode: Mode,
+        site: &'a str,
+    }
+
+    fn report() -> Report<'static> {
+        let channel = |v| Some(Bits::new(v).unwrap());
+        Report {
+            flags: [
+                true, false, false, true, true, true, false, false, true,
+                false, true, true,
+            ],
+            channels: [channel(4095), None, channel(0), None, None, channel(7)],
+            temperature: Bits::new(-215).unwrap(),
+            mode: Mode::Alarm {
+                level: Bits::new(-4).unwrap(),
+            },
+            site: "n7",
+        }
+    }
+
+    #[test]
+    fn lora_payload() {
+        // 12 + 6 + 3 * 12 + 11 + 3 + 3 bits, then 2 + 2 bytes
+        let mut buf = [0u8; 32];
+        assert_eq!(encode_packed(&report(), &mut buf), Ok(13));
+        assert_eq!(decode_packed::<Report>(&buf[..13]), Ok(report()));
+
+        // versus a byte per flag, option and tag, and whole integers
+        assert_eq!(encoded_size(&report()), Ok(12 + 6 + 3 * 2 + 2 + 2 + 4));
+    }
+
+    #[test]
+    fn bit_layout() {
+        let mut buf = [0u8; 8];
+        let value = (true, false, Bits::<u8, 3>::new(5).unwrap(), Some(true));
+        assert_eq!(encode_packed(&value, &mut buf), Ok(1));
+        assert_eq!(buf[0], 0b111_0101);
+
+        // strings start on a byte boundary
+        assert_eq!(encode_packed(&(true, "ab"), &mut buf), Ok(5));
+        assert_eq!(&buf[..5], &[1, 2, 0, b'a', b'b']);
+        assert_eq!(
+            decode_packed::<(bool, &str)>(&[3, 2, 0, b'a', b'b']),
+            Err(Error::InvalidRepresentation)
+        );
+    }
+
+    #[test]
+    fn enum_tags() {
+        let modes = [
+            (Mode::Idle, 0b1),
+            (Mode::Sampling(Bits::new(9).unwrap()), 0b100_1010),
+            (
+                Mode::Alarm {
+                    level: Bits::new(3).unwrap(),
+                },
+                0b01_1110,
+            ),
+        ];
+        for (mode, packed) in &modes {
+            let mut buf = [0u8; 1];
+            assert_eq!(encode_packed(mode, &mut buf), Ok(1));
+            assert_eq!(buf[0], *packed);
+            assert_eq!(decode_packed::<Mode>(&buf).as_ref(), Ok(mode));
+        }
+
+        // 8 zero bits, a one and 8 more bits for the last possible tag, while
+        // the next one would be out of range
+        let mut buf = [0u8; 3];
+        let mut serializer =
+            PackedSerializer::with_writer(SliceWriter::new(&mut buf));
+        serializer.write_tag(255).unwrap();
+        assert_eq!(serializer.finish().unwrap().len(), 3);
+        assert_eq!(buf, [0, 0x01, 0]);
+        assert_eq!(PackedDeserializer::new(&buf).read_tag(), Ok(255));
+        assert_eq!(
+            PackedDeserializer::new(&[0, 0x03, 0]).read_tag(),
+            Err(Error::InvalidRepresentation)
+        );
+        assert_eq!(
+            PackedDeserializer::new(&[0, 0xfe, 0xff]).read_tag(),
+            Err(Error::InvalidRepresentation)
+        );
+    }
+
+    #[test]
+    fn narrow_integers() {
+        assert_eq!(Bits::<u8, 3>::new(8), Err(Error::LengthExceeded));
+        assert_eq!(Bits::<i8, 3>::new(-5), Err(Error::LengthExceeded));
+        assert_eq!(Bits::<i8, 3>::new(-4).map(|b| b.get()), Ok(-4));
+        assert_eq!(Bits::<u64, 64>::new(u64::MAX).map(|b| *b), Ok(u64::MAX));
+
+        // the byte encoding ignores the width, but checks it when decoding
+        let mut buf = [0u8; 2];
+        encode(&Bits::<u16, 10>::new(1000).unwrap(), &mut buf).unwrap();
+        assert_eq!(buf, 1000u16.to_le_bytes());
+        assert_eq!(decode::<Bits<u8, 3>>(&[9]), Err(Error::LengthExceeded));
+    }
+
+    #[test]
+    fn errors() {
+        let mut buf = [0u8; 8];
+        assert_eq!(
+            encode_packed(&report(), &mut buf),
+            Err(Error::OutOfSpace {
+                needed: 13,
+                available: 8
+            })
+        );
+
+        let mut buf = [0u8; 32];
+        let len = encode_packed(&report(), &mut buf).unwrap();
+        assert_eq!(
+            decode_packed::<Report>(&buf[..len - 1]),
+            Err(Error::OutOfSpace {
+                needed: len,
+                available: len - 1
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_length() {
+        fn assert_packs_as<T: Serialize, U: Serialize>(value: &T, known: &U) {
+            let mut buf = [0u8; 64];
+            let len = encode_packed(value, &mut buf).unwrap();
+            let mut expected = [0u8; 64];
+            let expected_len = encode_packed(known, &mut expected).unwrap();
+            assert_eq!(&buf[..len], &expected[..expected_len]);
+            assert_eq!(packed_size(value), Ok(len));
+        }
+
+        // lengths at every bit offset, spanning two or three bytes
+        for bits in 0..8 {
+            let flags = &[true; 7][..bits];
+            assert_packs_as(
+                &(Unsized(flags), Evens(&DIGITS), true),
+                &(flags, &[0u8, 2, 4, 6, 8][..], true),
+            );
+        }
+
+        // nested, with lengths sharing bytes with each other
+        let inner = [Unsized(&[true, false][..]), Unsized(&[][..])];
+        let outer = [Unsized(&inner[..]), Unsized(&inner[..1])];
+        assert_packs_as(
+            &(true, Unsized(&outer[..]), Bits::<u8, 3>::new(5).unwrap()),
+            &(
+                true,
+                &[&[&[true, false][..], &[][..]][..], &[&[true, false][..]]][..],
+                Bits::<u8, 3>::new(5).unwrap(),
+            ),
+        );
+        let bytes = &[Unsized(&[1u8, 2][..]); 2][..];
+        assert_packs_as(&[Unsized(bytes); 1], &[&[&[1u8, 2][..]; 2][..]; 1]);
+
+        // strings through `collect_str`
+        assert_packs_as(
+            &(true, format_args!("n{}", 7), Some(3u8)),
+            &(true, "n7", Some(3u8)),
+        );
+        let mut buf = [0u8; 8];
+        let len =
+            encode_packed(&(true, format_args!("n{}", 7)), &mut buf).unwrap();
+        assert_eq!(decode_packed(&buf[..len]), Ok((true, "n7")));
+
+        // writers that can't patch
+        struct Stream;
+
+        impl Writer for Stream {
+            fn write(&mut self, _bytes: &[u8]) -> Result<(), Error> {
+                Ok(())
+            }
+        }
+
+        let mut serializer = PackedSerializer::with_writer(Stream);
+        assert_eq!(
+            Evens(&DIGITS).serialize(&mut serializer),
+            Err(Error::UnknownLength)
+        );
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct StatusV1 {
+        ok: bool,
+        level: Bits<u8, 3>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct StatusV2 {
+        ok: bool,
+        level: Bits<u8, 3>,
+        #[serde(default)]
+        alarm: bool,
+        #[serde(default)]
+        code: Option<Bits<u16, 10>>,
+    }
+
+    type Message<T> = (bool, Evolving<T>, Bits<u8, 4>);
+
+    fn message<T>(status: T) -> Message<T> {
+        (true, Evolving(status), Bits::new(9).unwrap())
+    }
+
+    #[test]
+    fn evolving() {
+        let v1 = || StatusV1 {
+            ok: true,
+            level: Bits::new(5).unwrap(),
+        };
+        let v2 = |alarm, code| StatusV2 {
+            ok: true,
+            level: Bits::new(5).unwrap(),
+            alarm,
+            code,
+        };
+
+        // a flag bit, the size at the next byte, 4 bits of fields and the
+        // 4 bits following them
+        let mut buf = [0u8; 8];
+        let len = encode_packed(&message(v1()), &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[1, 4, 0, 0b1001_1011]);
+        assert_eq!(packed_size(&message(v1())), Ok(len));
+        assert_eq!(decode_packed(&buf[..len]), Ok(message(v2(false, None))));
+
+        let code = Some(Bits::new(1000).unwrap());
+        let len = encode_packed(&message(v2(true, code)), &mut buf).unwrap();
+        assert_eq!(len, 1 + 2 + 2 + 1);
+        assert_eq!(decode_packed(&buf[..len]), Ok(message(v1())));
+        assert_eq!(decode_packed(&buf[..len]), Ok(message(v2(true, code))));
+
+        // sized in an extra pass by writers that can't patch
+        struct Stream([u8; 8], usize);
+
+        impl Writer for Stream {
+            fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+                self.0[self.1..self.1 + bytes.len()].copy_from_slice(bytes);
+                self.1 += bytes.len();
+                Ok(())
+            }
+        }
+
+        let mut serializer = PackedSerializer::with_writer(Stream([0; 8], 0));
+        message(v2(true, code)).serialize(&mut serializer).unwrap();
+        let stream = serializer.finish().unwrap();
+        assert_eq!(&stream.0[..stream.1], &buf[..len]);
+    }
+
+    /// Up to 8 elements decoded from a sequence
+    #[derive(Debug, Default, PartialEq)]
+    struct Options {
+        items: [Option<u16>; 8],
+        len: usize,
+    }
+
+    impl<'de> Deserialize<'de> for Options {
+        fn deserialize<D: serde::Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Self, D::Error> {
+            struct OptionsVisitor;
+
+            impl<'de> Visitor<'de> for OptionsVisitor {
+                type Value = Options;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("a sequence of options")
+                }
+
+                fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                    self,
+                    mut seq: A,
+                ) -> Result<Options, A::Error> {
+                    let mut options = Options::default();
+                    while let Some(item) = seq.next_element()? {
+                        options.items[options.len] = item;
+                        options.len += 1;
+                    }
+                    Ok(options)
+                }
+            }
+
+            deserializer.deserialize_seq(OptionsVisitor)
+        }
+    }
+
+    type Value<'a, S> = (
+        (bool, Option<bool>),
+        (u8, i16, u32, i64, u128, i128),
+        (Bits<u32, 17>, Bits<i8, 7>),
+        char,
+        &'a str,
+        S,
+    );
+
+    proptest! {
+        #[test]
+        fn round_trip(
+            flags in any::<(bool, Option<bool>)>(),
+            ints in any::<(u8, i16, u32, i64, u128, i128)>(),
+            narrow in (0u32..1 << 17, -64i8..64),
+            c in any::<char>(),
+            s in ".{0,12}",
+            seq in proptest::collection::vec(any::<Option<u16>>(), 0..8),
+        ) {
+            let narrow = (
+                Bits::new(narrow.0).unwrap(),
+                Bits::new(narrow.1).unwrap(),
+            );
+            let value: Value<&[Option<u16>]> =
+                (flags, ints, narrow, c, &s, &seq);
+            let mut buf = [0u8; 256];
+            let len = encode_packed(&value, &mut buf).unwrap();
+            prop_assert_eq!(Ok(len), packed_size(&value));
+
+            let mut options = Options::default();
+            options.items[..seq.len()].copy_from_slice(&seq);
+            options.len = seq.len();
+            let decoded = decode_packed::<Value<Options>>(&buf[..len]);
+            prop_assert_eq!(decoded, Ok((flags, ints, narrow, c, &*s, options)));
+        }
+    }
+}
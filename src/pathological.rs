@@ -1,4 +1,69 @@
-use serde::{Deserialize, Serialize};
+use core::fmt;
+
+use serde::de::{SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Consumes a sequence of `u16`s, keeping only its length
+#[derive(PartialEq, Debug)]
+pub struct SeqLen(pub usize);
+
+impl<'de> Deserialize<'de> for SeqLen {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct SeqLenVisitor;
+
+        impl<'de> Visitor<'de> for SeqLenVisitor {
+            type Value = SeqLen;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a sequence")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<SeqLen, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut len = 0;
+                while seq.next_element::<u16>()?.is_some() {
+                    len += 1;
+                }
+                Ok(SeqLen(len))
+            }
+        }
+
+        deserializer.deserialize_seq(SeqLenVisitor)
+    }
+}
+
+/// Serializes the even elements of a slice, without knowing how many
+pub struct Evens<'a, T>(pub &'a [T]);
+
+impl<T: Copy + Into<u64> + Serialize> Serialize for Evens<'_, T> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().filter(|n| (**n).into() % 2 == 0))
+    }
+}
+
+/// Serializes all elements of a slice, without knowing how many
+#[derive(Clone, Copy)]
+pub struct Unsized<'a, T>(pub &'a [T]);
+
+impl<T: Serialize> Serialize for Unsized<'_, T> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().filter(|_| true))
+    }
+}
+
+/// Digits of which `Evens` keeps the five even ones
+pub const DIGITS: [u8; 10] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
 
 #[derive(PartialEq, Debug, Serialize, Deserialize)]
 pub enum TooMany {
@@ -0,0 +1,225 @@
+//! Encoding straight into a circular transmit buffer.
+
+use serde::Serialize;
+
+use crate::ser::{Serializer, Writer};
+use crate::{encoded_size, Error};
+
+/// A circular buffer of encoded values waiting to be sent
+///
+/// Values are written after the unsent data, wrapping around the end of the
+/// buffer, and are only committed once fully encoded. Unsent data is never
+/// overwritten: a value that doesn't fit the free space fails with
+/// `Error::OutOfSpace` and leaves the buffer as it was.
+///
+/// ```
+/// let mut storage = [0u8; 6];
+/// let mut ring = fermion::RingBuffer::new(&mut storage);
+///
+/// ring.encode(&0x0102_0304u32).unwrap();
+/// ring.consume(3);
+/// ring.encode(&0x0506_0708u32).unwrap();
+/// assert_eq!(ring.committed(), (&[1, 8, 7][..], &[6, 5][..]));
+/// ```
+#[derive(Debug)]
+pub struct RingBuffer<'a> {
+    buf: &'a mut [u8],
+    // Start and length of the committed data
+    start: usize,
+    len: usize,
+}
+
+impl<'a> RingBuffer<'a> {
+    /// Creates an empty ring buffer over `buf`
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        RingBuffer {
+            buf,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Number of bytes that can be written without overwriting unsent data
+    pub fn free(&self) -> usize {
+        self.buf.len() - self.len
+    }
+
+    /// The committed, unsent data, split in two where it wraps around
+    pub fn committed(&self) -> (&[u8], &[u8]) {
+        let end = self.start + self.len;
+        if end <= self.buf.len() {
+            (&self.buf[self.start..end], &[])
+        } else {
+            (&self.buf[self.start..], &self.buf[..end - self.buf.len()])
+        }
+    }
+
+    /// Releases the first `count` committed bytes, once they were sent
+    pub fn consume(&mut self, count: usize) {
+        let count = count.min(self.len);
+        self.start = (self.start + count) % self.buf.len().max(1);
+        self.len -= count;
+    }
+
+    /// Returns a writer appending to the committed data
+    ///
+    /// Nothing it writes is committed until `RingWriter::commit`.
+    pub fn writer(&mut self) -> RingWriter<'_, 'a> {
+        RingWriter {
+            ring: self,
+            written: 0,
+        }
+    }
+
+    /// Encodes and commits a value, returning its encoded size
+    ///
+    /// On `Error::OutOfSpace`, `needed` is the full size of the value and
+    /// `available` the free space.
+    pub fn encode<T: Serialize>(&mut self, value: &T) -> Result<usize, Error> {
+        let mut serializer = Serializer::with_writer(self.writer());
+        match value.serialize(&mut serializer) {
+            Ok(()) => Ok(serializer.into_writer().commit()),
+            Err(Error::OutOfSpace { available, .. }) => {
+                Err(Error::OutOfSpace {
+                    needed: encoded_size(value)?,
+                    available,
+                })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Copies `bytes` to the position `at` bytes past the committed data
+    fn copy_at(&mut self, at: usize, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let capacity = self.buf.len();
+        let start = (self.start + self.len + at) % capacity;
+        let first = bytes.len().min(capacity - start);
+        self.buf[start..start + first].copy_from_slice(&bytes[..first]);
+        self.buf[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+    }
+}
+
+/// Writes into the free space of a `RingBuffer`
+pub struct RingWriter<'r, 'a> {
+    ring: &'r mut RingBuffer<'a>,
+    written: usize,
+}
+
+impl RingWriter<'_, '_> {
+    /// Commits everything written, returning its size
+    pub fn commit(self) -> usize {
+        self.ring.len += self.written;
+        self.written
+    }
+
+    fn claim(&mut self, len: usize) -> Result<usize, Error> {
+        let at = self.written;
+        if at + len > self.ring.free() {
+            return Err(Error::OutOfSpace {
+                needed: at + len,
+                available: self.ring.free(),
+            });
+        }
+        self.written += len;
+        Ok(at)
+    }
+}
+
+impl Writer for RingWriter<'_, '_> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let at = self.claim(bytes.len())?;
+        self.ring.copy_at(at, bytes);
+        Ok(())
+    }
+
+    fn reserve(&mut self, len: usize) -> Result<usize, Error> {
+        self.claim(len)
+    }
+
+    fn patch(&mut self, at: usize, bytes: &[u8]) -> Result<(), Error> {
+        self.ring.copy_at(at, bytes);
+        Ok(())
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathological::{Evens, DIGITS};
+    use crate::{decode, encode};
+
+    type Frame<'a> = (u16, &'a str);
+
+    /// Copies out the unsent data
+    fn unsent(ring: &RingBuffer) -> ([u8; 16], usize) {
+        let (first, second) = ring.committed();
+        let mut joined = [0u8; 16];
+        joined[..first.len()].copy_from_slice(first);
+        joined[first.len()..first.len() + second.len()].copy_from_slice(second);
+        (joined, first.len() + second.len())
+    }
+
+    #[test]
+    fn wraparound() {
+        let mut storage = [0u8; 16];
+        let mut ring = RingBuffer::new(&mut storage);
+        let mut sent = 0;
+
+        // frames of 8 bytes, consumed 5 at a time, wrap at every offset
+        for i in 0..32u16 {
+            let frame: Frame = (i, "abcd");
+            assert_eq!(ring.encode(&frame), Ok(8));
+
+            let mut expected = [0u8; 8];
+            encode(&frame, &mut expected).unwrap();
+            let (unsent, len) = unsent(&ring);
+            assert_eq!(&unsent[len - 8..len], &expected);
+
+            while ring.free() < 8 {
+                let count = ring.committed().0.len().min(5);
+                ring.consume(count);
+                sent += count;
+            }
+        }
+        assert!(sent > 16);
+    }
+
+    #[test]
+    fn never_clobbers() {
+        let mut storage = [0u8; 16];
+        let mut ring = RingBuffer::new(&mut storage);
+        ring.encode(&(1u16, "abcdefgh")).unwrap();
+        ring.consume(4);
+        ring.encode(&(2u16, "ab")).unwrap();
+        let before = unsent(&ring);
+
+        assert_eq!(
+            ring.encode(&(3u16, "abcdef")),
+            Err(Error::OutOfSpace {
+                needed: 10,
+                available: 2
+            })
+        );
+        assert_eq!(unsent(&ring), before);
+        assert_eq!(decode::<Frame>(&before.0[8..14]), Ok((2, "ab")));
+    }
+
+    #[test]
+    fn unknown_length() {
+        let mut storage = [0u8; 8];
+        let mut ring = RingBuffer::new(&mut storage);
+        ring.encode(&[0u8; 7]).unwrap();
+        ring.consume(7);
+
+        // the length prefix is patched across the wrap point
+        assert_eq!(ring.encode(&Evens(&DIGITS)), Ok(7));
+        assert_eq!(ring.committed(), (&[5][..], &[0, 0, 2, 4, 6, 8][..]));
+    }
+}
@@ -1,131 +1,620 @@
 use encode_unicode::CharExt;
 use serde::Serialize;
 
+use crate::bounded::SHORT_PREFIX;
+use crate::evolving::EVOLVING;
 use crate::Error;
 
-pub struct Serializer<'a> {
+/// Destination for encoded bytes
+pub trait Writer {
+    /// Appends `bytes` to the output
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error>;
+
+    /// Appends `len` placeholder bytes to be filled in later by `patch`,
+    /// returning their position
+    ///
+    /// Needed for values whose length is only known once written, such as
+    /// sequences of unknown length. Writers that can't go back, like
+    /// streams, keep the default which fails with `Error::UnknownLength`.
+    fn reserve(&mut self, _len: usize) -> Result<usize, Error> {
+        Err(Error::UnknownLength)
+    }
+
+    /// Overwrites bytes reserved at position `at`
+    fn patch(&mut self, _at: usize, _bytes: &[u8]) -> Result<(), Error> {
+        Err(Error::UnknownLength)
+    }
+
+    /// Returns the number of bytes written so far, for writers that can
+    /// patch any of them
+    ///
+    /// Lets values written after their size, such as `Evolving`, patch it
+    /// in rather than sizing the value in an extra pass. The position must
+    /// be the one `reserve` would return.
+    fn position(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl<W: Writer + ?Sized> Writer for &mut W {
+    #[inline(always)]
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        (**self).write(bytes)
+    }
+
+    fn reserve(&mut self, len: usize) -> Result<usize, Error> {
+        (**self).reserve(len)
+    }
+
+    fn patch(&mut self, at: usize, bytes: &[u8]) -> Result<(), Error> {
+        (**self).patch(at, bytes)
+    }
+
+    fn position(&self) -> Option<usize> {
+        (**self).position()
+    }
+}
+
+/// Writes into a byte buffer, failing with `Error::OutOfSpace` when full
+///
+/// The `needed` size reported is that of the failing write, the value being
+/// encoded may need more.
+pub struct SliceWriter<'a> {
     buf: &'a mut [u8],
     ofs: usize,
 }
 
-impl<'a> Serializer<'a> {
+impl<'a> SliceWriter<'a> {
+    /// Creates a writer starting at the beginning of `buf`
     pub fn new(buf: &'a mut [u8]) -> Self {
-        Serializer { buf, ofs: 0 }
+        SliceWriter { buf, ofs: 0 }
+    }
+
+    /// Number of bytes written so far
+    pub fn len(&self) -> usize {
+        self.ofs
+    }
+
+    /// Returns `true` if nothing has been written yet
+    pub fn is_empty(&self) -> bool {
+        self.ofs == 0
     }
+}
 
+impl Writer for SliceWriter<'_> {
     #[inline(always)]
-    fn assert_space(&self, space: usize) -> Result<(), Error> {
-        if self.ofs + space > self.buf.len() {
-            Err(Error::OutOfSpace)
-        } else {
-            Ok(())
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.ofs + bytes.len();
+        if end > self.buf.len() {
+            return Err(Error::OutOfSpace {
+                needed: end,
+                available: self.buf.len(),
+            });
+        }
+        unsafe {
+            self.buf
+                .get_unchecked_mut(self.ofs..end)
+                .copy_from_slice(bytes);
         }
+        self.ofs = end;
+        Ok(())
+    }
+
+    fn reserve(&mut self, len: usize) -> Result<usize, Error> {
+        let at = self.ofs;
+        let end = at + len;
+        if end > self.buf.len() {
+            return Err(Error::OutOfSpace {
+                needed: end,
+                available: self.buf.len(),
+            });
+        }
+        self.ofs = end;
+        Ok(at)
+    }
+
+    fn patch(&mut self, at: usize, bytes: &[u8]) -> Result<(), Error> {
+        self.buf[at..at + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.ofs)
+    }
+}
+
+/// Counts the encoded size without writing anything
+#[derive(Default)]
+pub struct SizeCounter {
+    size: usize,
+}
+
+impl SizeCounter {
+    /// Number of bytes counted so far
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Writer for SizeCounter {
+    #[inline(always)]
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.size += bytes.len();
+        Ok(())
+    }
+
+    fn reserve(&mut self, len: usize) -> Result<usize, Error> {
+        let at = self.size;
+        self.size += len;
+        Ok(at)
+    }
+
+    fn patch(&mut self, _at: usize, _bytes: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.size)
+    }
+}
+
+/// Writes into a buffer already sized by a `SizeCounter` pass, without
+/// checking for space.
+///
+/// Writes never fail, so the error checks after every field compile away.
+/// The value must not encode longer than it was counted.
+pub(crate) struct Presized<'a> {
+    buf: &'a mut [u8],
+    ofs: usize,
+}
+
+impl<'a> Presized<'a> {
+    /// Safety: every byte written must fit `buf`
+    pub(crate) unsafe fn new(buf: &'a mut [u8]) -> Self {
+        Presized { buf, ofs: 0 }
     }
 
+    /// Number of bytes written
+    pub(crate) fn len(&self) -> usize {
+        self.ofs
+    }
+}
+
+impl Writer for Presized<'_> {
     #[inline(always)]
-    fn write_u8(&mut self, v: u8) -> Result<(), Error> {
-        self.assert_space(1)?;
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.ofs + bytes.len();
         unsafe {
-            *self.buf.get_unchecked_mut(self.ofs) = v;
+            self.buf
+                .get_unchecked_mut(self.ofs..end)
+                .copy_from_slice(bytes);
         }
-        self.ofs += 1;
+        self.ofs = end;
         Ok(())
     }
 
-    #[inline(always)]
-    fn write_str(&mut self, s: &str) -> Result<(), Error> {
-        let bytes = s.as_bytes();
-        let len = bytes.len();
-        self.assert_space(len + 2)?;
+    fn reserve(&mut self, len: usize) -> Result<usize, Error> {
+        let at = self.ofs;
+        self.ofs += len;
+        Ok(at)
+    }
 
-        // write length
+    fn patch(&mut self, at: usize, bytes: &[u8]) -> Result<(), Error> {
         unsafe {
-            *self.buf.get_unchecked_mut(self.ofs) = (len & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 1) = (len >> 8 & 0xFF) as u8;
+            self.buf
+                .get_unchecked_mut(at..at + bytes.len())
+                .copy_from_slice(bytes);
         }
+        Ok(())
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.ofs)
+    }
+}
+
+/// Most bytes a `BitWriter` holds on to while patching bit fields
+const MAX_HELD: usize = 8;
+
+/// A byte shared by a bit field waiting to be patched and other bits
+#[derive(Clone, Copy, Default)]
+struct Held {
+    at: usize,
+    // The byte as written, once it was
+    value: u8,
+    // Bit fields still to be patched in it
+    fields: u8,
+}
 
-        let start = self.ofs + 2;
-        let end = start + len;
-        self.buf[start..end].clone_from_slice(bytes);
+/// Packs bits into a `Writer`, least significant bit first, for the packed
+/// encoding
+pub(crate) struct BitWriter<W> {
+    out: W,
+    // Bits not yet making up a whole byte, and how many there are
+    pending: u8,
+    count: u32,
+    // Bytes partly covered by reserved bit fields, in the order written
+    held: [Held; MAX_HELD],
+    held_len: usize,
+}
 
-        self.ofs += len + 2;
+impl<W: Writer> BitWriter<W> {
+    pub(crate) fn new(out: W) -> Self {
+        BitWriter {
+            out,
+            pending: 0,
+            count: 0,
+            held: [Held::default(); MAX_HELD],
+            held_len: 0,
+        }
+    }
+
+    /// Writes the low `width` bits of `value`
+    pub(crate) fn write_bits(
+        &mut self,
+        mut value: u64,
+        mut width: u32,
+    ) -> Result<(), Error> {
+        while width > 0 {
+            let take = width.min(8 - self.count);
+            self.pending |= ((value & ((1 << take) - 1)) as u8) << self.count;
+            self.count += take;
+            value >>= take;
+            width -= take;
+            if self.count == 8 {
+                self.flush()?;
+            }
+        }
         Ok(())
     }
 
-    #[inline(always)]
-    fn write_u16(&mut self, v: u16) -> Result<(), Error> {
-        self.assert_space(2)?;
-        unsafe {
-            *self.buf.get_unchecked_mut(self.ofs) = (v & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 1) = (v >> 8 & 0xFF) as u8;
+    /// Writes out the pending byte, keeping it if a bit field shares it
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.held_len > 0 {
+            let pending = self.pending;
+            let at = self.out.position().ok_or(Error::UnknownLength)?;
+            if let Some(held) = self.held_mut(at) {
+                held.value = pending;
+            }
         }
-        self.ofs += 2;
+        self.out.write(&[self.pending])?;
+        self.pending = 0;
+        self.count = 0;
         Ok(())
     }
 
-    #[inline(always)]
-    fn write_u32(&mut self, v: u32) -> Result<(), Error> {
-        self.assert_space(4)?;
-        unsafe {
-            *self.buf.get_unchecked_mut(self.ofs) = (v & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 1) = (v >> 8 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 2) = (v >> 16 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 3) = (v >> 24 & 0xFF) as u8;
+    /// Writes `width` zero bits for `patch_bits` to fill in later, and
+    /// returns their position
+    pub(crate) fn reserve_bits(&mut self, width: u32) -> Result<usize, Error> {
+        let at = self.position().ok_or(Error::UnknownLength)?;
+        let end = at + width as usize;
+        if !at.is_multiple_of(8) {
+            self.hold(at / 8)?;
+        }
+        if !end.is_multiple_of(8) {
+            self.hold(end / 8)?;
+        }
+        self.write_bits(0, width)?;
+        Ok(at)
+    }
+
+    /// Fills in `width` bits reserved at position `at` with `value`
+    pub(crate) fn patch_bits(
+        &mut self,
+        at: usize,
+        value: u64,
+        width: u32,
+    ) -> Result<(), Error> {
+        let end = at + width as usize;
+        let pending = self.out.position().ok_or(Error::UnknownLength)?;
+        for byte in at / 8..=(end - 1) / 8 {
+            let from = at.max(byte * 8);
+            let to = end.min(byte * 8 + 8);
+            let bits = (value >> (from - at)) & ((1 << (to - from)) - 1);
+            let bits = (bits as u8) << (from - byte * 8);
+            if byte == pending {
+                self.pending |= bits;
+            } else if let Some(held) = self.held_mut(byte) {
+                held.value |= bits;
+                let value = held.value;
+                self.out.patch(byte, &[value])?;
+            } else {
+                self.out.patch(byte, &[bits])?;
+            }
+        }
+        if !at.is_multiple_of(8) {
+            self.release(at / 8);
+        }
+        if !end.is_multiple_of(8) {
+            self.release(end / 8);
+        }
+        // fields are patched in the reverse order they were reserved
+        while self.held_len > 0 && self.held[self.held_len - 1].fields == 0 {
+            self.held_len -= 1;
         }
-        self.ofs += 4;
         Ok(())
     }
 
-    #[inline(always)]
-    fn write_u64(&mut self, v: u64) -> Result<(), Error> {
-        self.assert_space(8)?;
-        unsafe {
-            *self.buf.get_unchecked_mut(self.ofs) = (v & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 1) = (v >> 8 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 2) = (v >> 16 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 3) = (v >> 24 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 4) = (v >> 32 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 5) = (v >> 40 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 6) = (v >> 48 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 7) = (v >> 56 & 0xFF) as u8;
-        }
-        self.ofs += 8;
+    /// Keeps the byte at `at` once written, for a bit field sharing it
+    fn hold(&mut self, at: usize) -> Result<(), Error> {
+        if let Some(held) = self.held_mut(at) {
+            held.fields += 1;
+            return Ok(());
+        }
+        if self.held_len == MAX_HELD {
+            return Err(Error::UnknownLength);
+        }
+        self.held[self.held_len] = Held {
+            at,
+            value: 0,
+            fields: 1,
+        };
+        self.held_len += 1;
         Ok(())
     }
 
-    #[inline(always)]
-    fn write_u128(&mut self, v: u128) -> Result<(), Error> {
-        self.assert_space(16)?;
-        unsafe {
-            *self.buf.get_unchecked_mut(self.ofs) = (v & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 1) = (v >> 8 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 2) = (v >> 16 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 3) = (v >> 24 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 4) = (v >> 32 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 5) = (v >> 40 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 6) = (v >> 48 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 7) = (v >> 56 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 8) = (v >> 64 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 9) = (v >> 72 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 10) = (v >> 80 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 11) = (v >> 88 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 12) = (v >> 96 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 13) =
-                (v >> 104 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 14) =
-                (v >> 112 & 0xFF) as u8;
-            *self.buf.get_unchecked_mut(self.ofs + 15) =
-                (v >> 120 & 0xFF) as u8;
-        }
-        self.ofs += 16;
+    fn release(&mut self, at: usize) {
+        if let Some(held) = self.held_mut(at) {
+            held.fields -= 1;
+        }
+    }
+
+    fn held_mut(&mut self, at: usize) -> Option<&mut Held> {
+        self.held[..self.held_len]
+            .iter_mut()
+            .find(|held| held.at == at)
+    }
+
+    /// Pads with zero bits up to the next byte boundary
+    pub(crate) fn align(&mut self) -> Result<(), Error> {
+        if self.count > 0 {
+            self.flush()?;
+        }
         Ok(())
     }
+
+    /// Writes whole bytes, starting at the next byte boundary
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.align()?;
+        self.out.write(bytes)
+    }
+
+    /// Number of bits written so far, if the writer keeps count
+    pub(crate) fn position(&self) -> Option<usize> {
+        Some(self.out.position()? * 8 + self.count as usize)
+    }
+
+    /// Reserves `len` bytes at the next byte boundary
+    pub(crate) fn reserve(&mut self, len: usize) -> Result<usize, Error> {
+        self.align()?;
+        self.out.reserve(len)
+    }
+
+    /// Overwrites bytes reserved at position `at`
+    pub(crate) fn patch(
+        &mut self,
+        at: usize,
+        bytes: &[u8],
+    ) -> Result<(), Error> {
+        self.out.patch(at, bytes)
+    }
+
+    /// Writes out the last partial byte and returns the underlying writer
+    pub(crate) fn finish(mut self) -> Result<W, Error> {
+        self.align()?;
+        Ok(self.out)
+    }
+}
+
+/// Runs `f` into `out`, or reports the full size it needs if too small
+pub(crate) fn write_to_slice(
+    f: impl Fn(&mut dyn Writer) -> Result<(), Error>,
+    out: &mut [u8],
+) -> Result<usize, Error> {
+    let mut writer = SliceWriter::new(out);
+    match f(&mut writer) {
+        Ok(()) => Ok(writer.len()),
+        Err(Error::OutOfSpace { available, .. }) => {
+            let mut counter = SizeCounter::default();
+            f(&mut counter)?;
+            Err(Error::OutOfSpace {
+                needed: counter.size(),
+                available,
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[inline(always)]
+fn len_prefix(len: usize, short: bool) -> Result<([u8; 2], usize), Error> {
+    if short {
+        if len > u8::MAX as usize {
+            return Err(Error::LengthExceeded);
+        }
+        Ok(([len as u8, 0], 1))
+    } else {
+        if len > u16::MAX as usize {
+            return Err(Error::LengthExceeded);
+        }
+        Ok(((len as u16).to_le_bytes(), 2))
+    }
+}
+
+/// A length prefix waiting to be patched
+#[derive(Clone, Copy)]
+struct Reserved {
+    at: usize,
+    short: bool,
+}
+
+/// Writes the elements of a sequence, patching in its length at the end if
+/// it wasn't known up front
+pub struct SeqSerializer<'a, W> {
+    serializer: &'a mut Serializer<W>,
+    unknown: Option<(Reserved, usize)>,
+}
+
+/// Formats straight into a `Writer`, keeping track of the length written
+struct FmtWriter<'a, W> {
+    out: &'a mut W,
+    len: usize,
+    error: Option<Error>,
+}
+
+impl<W: Writer> core::fmt::Write for FmtWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        match self.out.write(s.as_bytes()) {
+            Ok(()) => {
+                self.len += s.len();
+                Ok(())
+            }
+            Err(e) => {
+                self.error = Some(e);
+                Err(core::fmt::Error)
+            }
+        }
+    }
+}
+
+/// Writes the fermion encoding into a `Writer`
+pub struct Serializer<W> {
+    out: W,
+    // Set by a `SHORT_PREFIX` newtype, consumed by the next length prefix
+    short_prefix: bool,
+}
+
+impl<'a> Serializer<SliceWriter<'a>> {
+    /// Creates a serializer writing to the start of `buf`
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Serializer::with_writer(SliceWriter::new(buf))
+    }
+}
+
+impl<W: Writer> Serializer<W> {
+    /// Creates a serializer writing to `out`
+    pub fn with_writer(out: W) -> Self {
+        Serializer {
+            out,
+            short_prefix: false,
+        }
+    }
+
+    /// Returns the underlying writer
+    pub fn into_writer(self) -> W {
+        self.out
+    }
+
+    /// Writes a single byte
+    #[inline(always)]
+    pub fn write_u8(&mut self, v: u8) -> Result<(), Error> {
+        self.out.write(&[v])
+    }
+
+    #[inline(always)]
+    fn write_len(&mut self, len: usize) -> Result<(), Error> {
+        let short = core::mem::replace(&mut self.short_prefix, false);
+        let (prefix, size) = len_prefix(len, short)?;
+        self.out.write(&prefix[..size])
+    }
+
+    /// Reserves a length prefix, to be filled in by `patch_len`
+    fn reserve_len(&mut self) -> Result<Reserved, Error> {
+        let short = core::mem::replace(&mut self.short_prefix, false);
+        let at = self.out.reserve(if short { 1 } else { 2 })?;
+        Ok(Reserved { at, short })
+    }
+
+    fn patch_len(
+        &mut self,
+        reserved: Reserved,
+        len: usize,
+    ) -> Result<(), Error> {
+        let (prefix, size) = len_prefix(len, reserved.short)?;
+        self.out.patch(reserved.at, &prefix[..size])
+    }
+
+    /// Writes a value after its size, patched in if the writer can
+    fn write_evolving<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        if self.out.position().is_some() {
+            let reserved = self.reserve_len()?;
+            value.serialize(&mut *self)?;
+            let start = reserved.at + if reserved.short { 1 } else { 2 };
+            let end = self.out.position().ok_or(Error::UnknownLength)?;
+            return self.patch_len(reserved, end - start);
+        }
+        let mut counter = Serializer::with_writer(SizeCounter::default());
+        value.serialize(&mut counter)?;
+        self.write_len(counter.into_writer().size())?;
+        value.serialize(self)
+    }
+
+    /// Writes a length-prefixed byte slice
+    #[inline(always)]
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.write_len(bytes.len())?;
+        self.out.write(bytes)
+    }
+
+    /// Writes a byte slice of at most `bound` bytes, as `BoundedBytes` does
+    #[inline(always)]
+    pub(crate) fn write_bounded(
+        &mut self,
+        bytes: &[u8],
+        bound: usize,
+    ) -> Result<(), Error> {
+        if bytes.len() > bound {
+            return Err(Error::LengthExceeded);
+        }
+        self.short_prefix = bound <= u8::MAX as usize;
+        self.write_bytes(bytes)
+    }
+
+    /// Writes a length-prefixed `str`
+    #[inline(always)]
+    pub fn write_str(&mut self, s: &str) -> Result<(), Error> {
+        self.write_bytes(s.as_bytes())
+    }
+
+    /// Writes a UTF-8 encoded `char`
+    #[inline(always)]
+    pub fn write_char(&mut self, v: char) -> Result<(), Error> {
+        let (arr, sz) = v.to_utf8_array();
+        self.out.write(&arr[..sz])
+    }
+
+    /// Writes a little-endian `u16`
+    #[inline(always)]
+    pub fn write_u16(&mut self, v: u16) -> Result<(), Error> {
+        self.out.write(&v.to_le_bytes())
+    }
+
+    /// Writes a little-endian `u32`
+    #[inline(always)]
+    pub fn write_u32(&mut self, v: u32) -> Result<(), Error> {
+        self.out.write(&v.to_le_bytes())
+    }
+
+    /// Writes a little-endian `u64`
+    #[inline(always)]
+    pub fn write_u64(&mut self, v: u64) -> Result<(), Error> {
+        self.out.write(&v.to_le_bytes())
+    }
+
+    /// Writes a little-endian `u128`
+    #[inline(always)]
+    pub fn write_u128(&mut self, v: u128) -> Result<(), Error> {
+        self.out.write(&v.to_le_bytes())
+    }
 }
 
-impl<'b, 'a> serde::ser::Serializer for &'a mut Serializer<'b> {
+impl<'a, W: Writer> serde::ser::Serializer for &'a mut Serializer<W> {
     type Ok = ();
     type Error = Error;
-    type SerializeSeq = Self;
+    type SerializeSeq = SeqSerializer<'a, W>;
     type SerializeTuple = Self;
     type SerializeTupleStruct = Self;
     type SerializeTupleVariant = Self;
@@ -186,36 +675,24 @@ impl<'b, 'a> serde::ser::Serializer for &'a mut Serializer<'b> {
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
-        let (arr, sz) = v.to_utf8_array();
-        self.assert_space(sz)?;
-        for (i, c) in arr[..sz].iter().enumerate() {
-            unsafe {
-                *self.buf.get_unchecked_mut(self.ofs + i) = *c;
-            }
-        }
-        self.ofs += sz;
-        Ok(())
+        self.write_char(v)
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
         self.write_str(v)
     }
 
-    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Self::Error> {
-        // Placeholder for specialization?
-        unimplemented!()
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        self.write_bytes(v)
     }
 
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
         self.write_u8(0)
     }
 
-    fn serialize_some<T: ?Sized>(
-        self,
-        value: &T,
-    ) -> Result<Self::Ok, Self::Error>
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         self.write_u8(1)?;
         value.serialize(self)
@@ -244,18 +721,23 @@ impl<'b, 'a> serde::ser::Serializer for &'a mut Serializer<'b> {
         self.write_u8(variant_index as u8)
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(
+    fn serialize_newtype_struct<T>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
+        if name == SHORT_PREFIX {
+            self.short_prefix = true;
+        } else if name == EVOLVING {
+            return self.write_evolving(value);
+        }
         value.serialize(self)
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         variant_index: u32,
@@ -263,7 +745,7 @@ impl<'b, 'a> serde::ser::Serializer for &'a mut Serializer<'b> {
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         if variant_index > 255 {
             return Err(Error::TooManyVariants);
@@ -276,13 +758,17 @@ impl<'b, 'a> serde::ser::Serializer for &'a mut Serializer<'b> {
         self,
         len: Option<usize>,
     ) -> Result<Self::SerializeSeq, Self::Error> {
-        let len = len.unwrap_or(0);
-        if len > core::u16::MAX as usize {
-            Err(Error::LengthExceeded)
-        } else {
-            self.write_u16(len as u16)?;
-            Ok(self)
-        }
+        let unknown = match len {
+            Some(len) => {
+                self.write_len(len)?;
+                None
+            }
+            None => Some((self.reserve_len()?, 0)),
+        };
+        Ok(SeqSerializer {
+            serializer: self,
+            unknown,
+        })
     }
 
     fn serialize_tuple(
@@ -343,11 +829,22 @@ impl<'b, 'a> serde::ser::Serializer for &'a mut Serializer<'b> {
         Ok(self)
     }
 
-    fn collect_str<T: ?Sized>(self, _value: &T) -> Result<Self::Ok, Self::Error>
+    fn collect_str<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
     where
-        T: core::fmt::Display,
+        T: ?Sized + core::fmt::Display,
     {
-        Err(Error::NotSupported)
+        let reserved = self.reserve_len()?;
+        let mut fmt_writer = FmtWriter {
+            out: &mut self.out,
+            len: 0,
+            error: None,
+        };
+        if core::fmt::write(&mut fmt_writer, format_args!("{}", value)).is_err()
+        {
+            return Err(fmt_writer.error.unwrap_or(Error::Custom));
+        }
+        let len = fmt_writer.len;
+        self.patch_len(reserved, len)
     }
 
     #[inline]
@@ -356,17 +853,17 @@ impl<'b, 'a> serde::ser::Serializer for &'a mut Serializer<'b> {
     }
 }
 
-impl<'b, 'a: 'b> serde::ser::SerializeStructVariant for &'b mut Serializer<'a> {
+impl<W: Writer> serde::ser::SerializeStructVariant for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(
+    fn serialize_field<T>(
         &mut self,
         _key: &'static str,
         value: &T,
     ) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -376,17 +873,17 @@ impl<'b, 'a: 'b> serde::ser::SerializeStructVariant for &'b mut Serializer<'a> {
     }
 }
 
-impl<'b, 'a: 'b> serde::ser::SerializeStruct for &'b mut Serializer<'a> {
+impl<W: Writer> serde::ser::SerializeStruct for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(
+    fn serialize_field<T>(
         &mut self,
         _key: &'static str,
         value: &T,
     ) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -396,16 +893,13 @@ impl<'b, 'a: 'b> serde::ser::SerializeStruct for &'b mut Serializer<'a> {
     }
 }
 
-impl<'b, 'a: 'b> serde::ser::SerializeTupleVariant for &'b mut Serializer<'a> {
+impl<W: Writer> serde::ser::SerializeTupleVariant for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(
-        &mut self,
-        value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -415,16 +909,13 @@ impl<'b, 'a: 'b> serde::ser::SerializeTupleVariant for &'b mut Serializer<'a> {
     }
 }
 
-impl<'b, 'a: 'b> serde::ser::SerializeTupleStruct for &'b mut Serializer<'a> {
+impl<W: Writer> serde::ser::SerializeTupleStruct for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(
-        &mut self,
-        value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -434,16 +925,13 @@ impl<'b, 'a: 'b> serde::ser::SerializeTupleStruct for &'b mut Serializer<'a> {
     }
 }
 
-impl<'b, 'a: 'b> serde::ser::SerializeTuple for &'b mut Serializer<'a> {
+impl<W: Writer> serde::ser::SerializeTuple for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(
-        &mut self,
-        value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -453,21 +941,24 @@ impl<'b, 'a: 'b> serde::ser::SerializeTuple for &'b mut Serializer<'a> {
     }
 }
 
-impl<'b, 'a: 'b> serde::ser::SerializeSeq for &'b mut Serializer<'a> {
+impl<W: Writer> serde::ser::SerializeSeq for SeqSerializer<'_, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(
-        &mut self,
-        value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        if let Some((_, len)) = &mut self.unknown {
+            *len += 1;
+        }
+        value.serialize(&mut *self.serializer)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        match self.unknown {
+            Some((reserved, len)) => self.serializer.patch_len(reserved, len),
+            None => Ok(()),
+        }
     }
 }
@@ -0,0 +1,164 @@
+//! A `tokio_util` codec for length-prefixed fermion frames.
+
+use std::io;
+use std::marker::PhantomData;
+
+use bytes::{Buf, BufMut, BytesMut};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{decode_whole, encode, encoded_size, Error};
+
+const LEN_SIZE: usize = 2;
+
+fn invalid_data(e: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Encodes and decodes values of type `T` as frames prefixed with their
+/// `u16` little-endian length
+///
+/// Frames larger than the maximum size, or that fail to encode or decode,
+/// are `io::ErrorKind::InvalidData` errors wrapping the fermion `Error`.
+///
+/// ```
+/// use bytes::BytesMut;
+/// use fermion::FermionCodec;
+/// use tokio_util::codec::{Decoder, Encoder};
+///
+/// let mut codec = FermionCodec::<(u8, u32)>::new();
+/// let mut buf = BytesMut::new();
+/// codec.encode((1, 2), &mut buf).unwrap();
+/// assert_eq!(&buf[..], &[5, 0, 1, 2, 0, 0, 0]);
+/// assert_eq!(codec.decode(&mut buf).unwrap(), Some((1, 2)));
+/// ```
+#[derive(Debug)]
+pub struct FermionCodec<T> {
+    max_frame: usize,
+    message: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for FermionCodec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for FermionCodec<T> {
+    fn clone(&self) -> Self {
+        Self::with_max_frame(self.max_frame)
+    }
+}
+
+impl<T> FermionCodec<T> {
+    /// Creates a codec accepting frames of any size the prefix can hold
+    pub fn new() -> Self {
+        Self::with_max_frame(u16::MAX as usize)
+    }
+
+    /// Creates a codec rejecting values encoding to more than `max_frame`
+    /// bytes
+    pub fn with_max_frame(max_frame: usize) -> Self {
+        FermionCodec {
+            max_frame: max_frame.min(u16::MAX as usize),
+            message: PhantomData,
+        }
+    }
+}
+
+impl<T: Serialize> Encoder<T> for FermionCodec<T> {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> io::Result<()> {
+        let size = encoded_size(&item).map_err(invalid_data)?;
+        if size > self.max_frame {
+            return Err(invalid_data(Error::LengthExceeded));
+        }
+        dst.reserve(LEN_SIZE + size);
+        dst.put_u16_le(size as u16);
+        let start = dst.len();
+        dst.resize(start + size, 0);
+        encode(&item, &mut dst[start..]).map_err(invalid_data)?;
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned> Decoder for FermionCodec<T> {
+    type Item = T;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<T>> {
+        if src.len() < LEN_SIZE {
+            return Ok(None);
+        }
+        let size = u16::from_le_bytes([src[0], src[1]]) as usize;
+        if size > self.max_frame {
+            return Err(invalid_data(Error::LengthExceeded));
+        }
+        if src.len() < LEN_SIZE + size {
+            src.reserve(LEN_SIZE + size - src.len());
+            return Ok(None);
+        }
+
+        src.advance(LEN_SIZE);
+        let frame = src.split_to(size);
+        decode_whole(&frame).map(Some).map_err(invalid_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    type Message = (u32, Option<[u16; 3]>, bool);
+
+    const MESSAGES: [Message; 3] = [
+        (1, Some([1, 2, 3]), true),
+        (2, None, false),
+        (3, None, true),
+    ];
+
+    #[tokio::test]
+    async fn duplex() {
+        let (client, server) = tokio::io::duplex(8);
+        let mut sink = FramedWrite::new(client, FermionCodec::new());
+        let mut stream =
+            FramedRead::new(server, FermionCodec::<Message>::new());
+
+        let send = async {
+            for message in MESSAGES {
+                sink.send(message).await.unwrap();
+            }
+        };
+        let receive = async {
+            for message in MESSAGES {
+                assert_eq!(stream.next().await.unwrap().unwrap(), message);
+            }
+        };
+        tokio::join!(send, receive);
+    }
+
+    #[tokio::test]
+    async fn max_frame() {
+        let (client, server) = tokio::io::duplex(64);
+        let codec = FermionCodec::<Message>::with_max_frame(8);
+
+        let mut limited = FramedWrite::new(client, codec.clone());
+        let error = limited.send(MESSAGES[0]).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // sent without a limit, the frame is rejected by the receiver
+        let client = limited.into_inner();
+        let mut unlimited = FramedWrite::new(client, FermionCodec::new());
+        unlimited.send(MESSAGES[0]).await.unwrap();
+
+        let mut stream = FramedRead::new(server, codec);
+        let error = stream.next().await.unwrap().unwrap_err();
+        let inner = error.get_ref().unwrap().downcast_ref::<Error>();
+        assert_eq!(inner, Some(&Error::LengthExceeded));
+    }
+}
@@ -0,0 +1,121 @@
+//! Serde `with` adapters for slowly changing sequences of integers, such as
+//! timestamps and samples in telemetry.
+//!
+//! The adapters work field by field, in fermion and in other formats:
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, PartialEq, Serialize, Deserialize)]
+//! struct Telemetry {
+//!     #[serde(with = "fermion::with::delta")]
+//!     timestamps: [u32; 64],
+//!     #[serde(with = "fermion::with::rle")]
+//!     samples: [u16; 64],
+//! }
+//!
+//! let mut telemetry = Telemetry {
+//!     timestamps: [0; 64],
+//!     samples: [20; 64],
+//! };
+//! for (i, t) in telemetry.timestamps.iter_mut().enumerate() {
+//!     *t = 1_700_000_000 + i as u32 * 60;
+//! }
+//! telemetry.samples[40..].fill(21);
+//!
+//! let mut buf = [0u8; 512];
+//! let len = fermion::encode(&telemetry, &mut buf).unwrap();
+//! // instead of 512 bytes
+//! assert_eq!(len, 2 + 5 + 63 + 2 + 2 * 3);
+//! assert_eq!(fermion::decode(&buf[..len]), Ok(telemetry));
+//! ```
+
+use core::marker::PhantomData;
+
+use serde::de;
+
+use crate::length_exceeded;
+
+pub mod delta;
+pub mod rle;
+
+/// A sequence the adapters can decode into
+///
+/// Arrays must decode exactly `N` items, `heapless::Vec` at most its
+/// capacity, with the `heapless` feature. Slices owned by the caller are
+/// decoded into through the `IntoSlice` seed of each adapter.
+pub trait Samples<T>: AsMut<[T]> + Sized {
+    /// Returns the sequence holding as many items as it can
+    fn full() -> Self;
+
+    /// Keeps the first `len` items, or returns `None` if that's too few
+    fn truncate(self, len: usize) -> Option<Self>;
+}
+
+impl<T: Default + Copy, const N: usize> Samples<T> for [T; N] {
+    fn full() -> Self {
+        [T::default(); N]
+    }
+
+    fn truncate(self, len: usize) -> Option<Self> {
+        if len == N {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<T: Default + Clone, const N: usize> Samples<T> for heapless::Vec<T, N> {
+    fn full() -> Self {
+        let mut samples = heapless::Vec::new();
+        let _ = samples.resize_default(N);
+        samples
+    }
+
+    fn truncate(mut self, len: usize) -> Option<Self> {
+        heapless::Vec::truncate(&mut self, len);
+        Some(self)
+    }
+}
+
+/// Decodes items into a `Samples`, one at a time
+struct Filling<S, T> {
+    samples: S,
+    len: usize,
+    items: PhantomData<T>,
+}
+
+impl<S: Samples<T>, T> Filling<S, T> {
+    fn new() -> Self {
+        Self::with(S::full())
+    }
+
+    fn finish<E: de::Error>(self) -> Result<S, E> {
+        self.samples
+            .truncate(self.len)
+            .ok_or_else(|| E::custom("fewer items than the array holds"))
+    }
+}
+
+impl<S: AsMut<[T]>, T> Filling<S, T> {
+    fn with(samples: S) -> Self {
+        Filling {
+            samples,
+            len: 0,
+            items: PhantomData,
+        }
+    }
+
+    fn push<E: de::Error>(&mut self, item: T) -> Result<(), E> {
+        match self.samples.as_mut().get_mut(self.len) {
+            Some(slot) => {
+                *slot = item;
+                self.len += 1;
+                Ok(())
+            }
+            None => Err(length_exceeded(self.len + 1)),
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Encodes a sequence of integers as its first value followed by the
+//! differences between consecutive values.
+//!
+//! Each value is a zigzag varint, so a difference between -64 and 63 takes
+//! a single byte. The varints are encoded as a sequence of bytes.
+
+use core::fmt;
+
+use serde::de::{self, DeserializeSeed, Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeSeq, Serializer};
+
+use super::{Filling, Samples};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Integers that can be delta encoded, up to 64 bits wide
+pub trait DeltaInt: sealed::Sealed + Copy + Default {
+    #[doc(hidden)]
+    fn delta(self, prev: Self) -> u64;
+
+    #[doc(hidden)]
+    fn undelta(prev: Self, delta: u64) -> Option<Self>;
+}
+
+macro_rules! delta_int {
+    ($($t:ty => $signed:ty),*) => {
+        $(
+            impl sealed::Sealed for $t {}
+
+            impl DeltaInt for $t {
+                fn delta(self, prev: Self) -> u64 {
+                    // wraps, so any difference fits the width of the type
+                    let delta = self.wrapping_sub(prev) as $signed as i64;
+                    ((delta << 1) ^ (delta >> 63)) as u64
+                }
+
+                fn undelta(prev: Self, delta: u64) -> Option<Self> {
+                    let delta = (delta >> 1) as i64 ^ -((delta & 1) as i64);
+                    let narrow = delta as $signed;
+                    if narrow as i64 == delta {
+                        Some(prev.wrapping_add(narrow as $t))
+                    } else {
+                        None
+                    }
+                }
+            }
+        )*
+    };
+}
+
+delta_int!(
+    u8 => i8, u16 => i16, u32 => i32, u64 => i64, usize => isize,
+    i8 => i8, i16 => i16, i32 => i32, i64 => i64, isize => isize
+);
+
+fn varint_len(value: u64) -> usize {
+    ((u64::BITS - value.leading_zeros()) as usize)
+        .div_ceil(7)
+        .max(1)
+}
+
+fn write_varint<S: SerializeSeq>(
+    seq: &mut S,
+    mut value: u64,
+) -> Result<(), S::Error> {
+    while value >= 0x80 {
+        seq.serialize_element(&(value as u8 | 0x80))?;
+        value >>= 7;
+    }
+    seq.serialize_element(&(value as u8))
+}
+
+/// Reads the rest of a varint starting with `byte`
+fn read_varint<'de, A: SeqAccess<'de>>(
+    seq: &mut A,
+    mut byte: u8,
+) -> Result<u64, A::Error> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        // a trailing zero byte, or bits past 64
+        if (shift > 0 && byte == 0) || (shift == 63 && byte > 1) {
+            return Err(de::Error::custom("non-canonical varint"));
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+        byte = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::custom("truncated varint"))?;
+    }
+}
+
+/// Serializes the items of an array or slice as deltas
+pub fn serialize<V, T, S>(items: &V, serializer: S) -> Result<S::Ok, S::Error>
+where
+    V: AsRef<[T]> + ?Sized,
+    T: DeltaInt,
+    S: Serializer,
+{
+    let items = items.as_ref();
+    let deltas = || {
+        items.iter().scan(T::default(), |prev, item| {
+            let delta = item.delta(*prev);
+            *prev = *item;
+            Some(delta)
+        })
+    };
+
+    let mut seq =
+        serializer.serialize_seq(Some(deltas().map(varint_len).sum()))?;
+    for delta in deltas() {
+        write_varint(&mut seq, delta)?;
+    }
+    seq.end()
+}
+
+struct DeltaVisitor<S, T>(Filling<S, T>);
+
+impl<'de, S, T> Visitor<'de> for DeltaVisitor<S, T>
+where
+    S: AsMut<[T]>,
+    T: DeltaInt,
+{
+    type Value = Filling<S, T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("delta encoded integers")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(
+        mut self,
+        mut seq: A,
+    ) -> Result<Filling<S, T>, A::Error> {
+        let mut prev = T::default();
+        while let Some(byte) = seq.next_element()? {
+            let delta = read_varint(&mut seq, byte)?;
+            prev = T::undelta(prev, delta)
+                .ok_or_else(|| de::Error::custom("delta out of range"))?;
+            self.0.push(prev)?;
+        }
+        Ok(self.0)
+    }
+}
+
+/// Deserializes delta encoded items into an array or `heapless::Vec`
+pub fn deserialize<'de, S, T, D>(deserializer: D) -> Result<S, D::Error>
+where
+    S: Samples<T>,
+    T: DeltaInt,
+    D: Deserializer<'de>,
+{
+    deserializer
+        .deserialize_seq(DeltaVisitor(Filling::new()))?
+        .finish()
+}
+
+/// Deserializes delta encoded items into the start of a slice, returning
+/// how many there were
+///
+/// Fails with `Error::LengthExceeded` in fermion if they don't fit.
+///
+/// ```
+/// use fermion::with::delta::IntoSlice;
+/// use serde::de::DeserializeSeed;
+/// use serde::Serialize;
+///
+/// #[derive(Serialize)]
+/// struct Series<'a> {
+///     #[serde(with = "fermion::with::delta")]
+///     timestamps: &'a [u32],
+/// }
+///
+/// let mut buf = [0u8; 16];
+/// let series = Series { timestamps: &[1000, 1010, 1020] };
+/// let len = fermion::encode(&series, &mut buf).unwrap();
+///
+/// let mut timestamps = [0u32; 8];
+/// let mut deserializer = fermion::Deserializer::new(&buf[..len]);
+/// let count = IntoSlice(&mut timestamps).deserialize(&mut deserializer);
+/// assert_eq!(count, Ok(3));
+/// assert_eq!(timestamps[..3], [1000, 1010, 1020]);
+/// ```
+pub struct IntoSlice<'a, T>(pub &'a mut [T]);
+
+impl<'de, T: DeltaInt> DeserializeSeed<'de> for IntoSlice<'_, T> {
+    type Value = usize;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<usize, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let visitor = DeltaVisitor(Filling::with(self.0));
+        Ok(deserializer.deserialize_seq(visitor)?.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode, encode, Deserializer as Fermion, Error};
+
+    use proptest::prelude::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Series<T: DeltaInt, const N: usize> {
+        #[serde(with = "super")]
+        items: [T; N],
+    }
+
+    #[derive(Serialize)]
+    struct Borrowed<'a> {
+        #[serde(with = "super")]
+        items: &'a [i16],
+    }
+
+    fn round_trip<T, const N: usize>(items: [T; N]) -> usize
+    where
+        T: DeltaInt + fmt::Debug + PartialEq + Serialize,
+    {
+        let series = Series { items };
+        let mut buf = [0u8; 1024];
+        let len = encode(&series, &mut buf).unwrap();
+        assert_eq!(decode(&buf[..len]), Ok(series));
+        len
+    }
+
+    #[test]
+    fn encoding() {
+        let mut buf = [0u8; 16];
+        let len = encode(
+            &Series {
+                items: [100u32, 101, 99, 99],
+            },
+            &mut buf,
+        );
+        assert_eq!(&buf[..len.unwrap()], &[5, 0, 200, 1, 2, 3, 0]);
+
+        // a negative first value, and deltas wrapping around
+        assert_eq!(round_trip([-1i8, 127, -128, 0]), 2 + 1 + 2 + 1 + 2);
+        assert_eq!(round_trip([u64::MAX, 0, u64::MAX]), 2 + 3);
+        assert_eq!(round_trip([0i64, i64::MAX, i64::MIN]), 2 + 1 + 10 + 1);
+        assert_eq!(round_trip::<u16, 0>([]), 2);
+
+        let len = encode(
+            &Borrowed {
+                items: &[-5, -4, -3],
+            },
+            &mut buf,
+        );
+        assert_eq!(
+            decode(&buf[..len.unwrap()]),
+            Ok(Series {
+                items: [-5i16, -4, -3]
+            })
+        );
+    }
+
+    #[test]
+    fn errors() {
+        // the wrong number of items
+        assert_eq!(
+            decode::<Series<u8, 2>>(&[3, 0, 2, 2, 2]),
+            Err(Error::LengthExceeded)
+        );
+        assert_eq!(decode::<Series<u8, 2>>(&[1, 0, 2]), Err(Error::Custom));
+
+        // truncated and overlong varints, and a delta too wide for the type
+        assert_eq!(decode::<Series<u32, 1>>(&[1, 0, 0x80]), Err(Error::Custom));
+        assert_eq!(
+            decode::<Series<u32, 1>>(&[2, 0, 0x81, 0]),
+            Err(Error::Custom)
+        );
+        assert_eq!(
+            decode::<Series<u8, 1>>(&[2, 0, 0x80, 2]),
+            Err(Error::Custom)
+        );
+    }
+
+    #[test]
+    fn into_slice() {
+        let mut buf = [0u8; 16];
+        let len = encode(
+            &Borrowed {
+                items: &[-5, -4, -3],
+            },
+            &mut buf,
+        )
+        .unwrap();
+
+        let mut items = [0i16; 4];
+        let seed = IntoSlice(&mut items);
+        assert_eq!(seed.deserialize(&mut Fermion::new(&buf[..len])), Ok(3));
+        assert_eq!(items, [-5, -4, -3, 0]);
+
+        let mut items = [0i16; 2];
+        let seed = IntoSlice(&mut items);
+        assert_eq!(
+            seed.deserialize(&mut Fermion::new(&buf[..len])),
+            Err(Error::LengthExceeded)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary(items in any::<[u32; 32]>(), signed in any::<[i64; 8]>()) {
+            round_trip(items);
+            round_trip(signed);
+        }
+
+        #[test]
+        fn slowly_changing(start in any::<u32>(), steps in any::<[i8; 32]>()) {
+            let mut items = [start; 32];
+            for i in 1..32 {
+                items[i] = items[i - 1].wrapping_add(steps[i] as u32);
+            }
+            prop_assert!(round_trip(items) <= 2 + 5 + 31 * 2);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Encodes a sequence as pairs of a value and the number of times it
+//! repeats, for sequences that hold a value for long stretches.
+//!
+//! Runs are counted in a `u8`, longer runs are split.
+
+use core::fmt;
+
+use serde::de::{
+    self, Deserialize, DeserializeSeed, Deserializer, SeqAccess, Visitor,
+};
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+use super::{Filling, Samples};
+
+/// Splits `items` in runs of equal values, at most 255 long
+fn runs<T: PartialEq>(items: &[T]) -> impl Iterator<Item = &[T]> {
+    items
+        .chunk_by(|a, b| a == b)
+        .flat_map(|run| run.chunks(u8::MAX as usize))
+}
+
+/// Serializes the items of an array or slice as runs
+pub fn serialize<V, T, S>(items: &V, serializer: S) -> Result<S::Ok, S::Error>
+where
+    V: AsRef<[T]> + ?Sized,
+    T: Serialize + PartialEq,
+    S: Serializer,
+{
+    let items = items.as_ref();
+    let mut seq = serializer.serialize_seq(Some(runs(items).count()))?;
+    for run in runs(items) {
+        seq.serialize_element(&(&run[0], run.len() as u8))?;
+    }
+    seq.end()
+}
+
+struct RunVisitor<S, T>(Filling<S, T>);
+
+impl<'de, S, T> Visitor<'de> for RunVisitor<S, T>
+where
+    S: AsMut<[T]>,
+    T: Deserialize<'de> + Clone,
+{
+    type Value = Filling<S, T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("runs of values")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(
+        mut self,
+        mut seq: A,
+    ) -> Result<Filling<S, T>, A::Error> {
+        while let Some((value, run)) = seq.next_element::<(T, u8)>()? {
+            if run == 0 {
+                return Err(de::Error::custom("empty run"));
+            }
+            for _ in 1..run {
+                self.0.push(value.clone())?;
+            }
+            self.0.push(value)?;
+        }
+        Ok(self.0)
+    }
+}
+
+/// Deserializes runs into an array or `heapless::Vec`
+pub fn deserialize<'de, S, T, D>(deserializer: D) -> Result<S, D::Error>
+where
+    S: Samples<T>,
+    T: Deserialize<'de> + Clone,
+    D: Deserializer<'de>,
+{
+    deserializer
+        .deserialize_seq(RunVisitor(Filling::new()))?
+        .finish()
+}
+
+/// Deserializes runs into the start of a slice, returning how many items
+/// they held
+///
+/// Fails with `Error::LengthExceeded` in fermion if they don't fit.
+pub struct IntoSlice<'a, T>(pub &'a mut [T]);
+
+impl<'de, T: Deserialize<'de> + Clone> DeserializeSeed<'de>
+    for IntoSlice<'_, T>
+{
+    type Value = usize;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<usize, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let visitor = RunVisitor(Filling::with(self.0));
+        Ok(deserializer.deserialize_seq(visitor)?.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IntoSlice;
+    use crate::{decode, encode, Deserializer as Fermion, Error};
+
+    use proptest::prelude::*;
+    use serde::de::DeserializeSeed;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Samples16<const N: usize> {
+        #[serde(with = "super")]
+        items: [u16; N],
+    }
+
+    fn round_trip<const N: usize>(items: [u16; N]) -> usize {
+        let samples = Samples16 { items };
+        let mut buf = [0u8; 2048];
+        let len = encode(&samples, &mut buf).unwrap();
+        assert_eq!(decode(&buf[..len]), Ok(samples));
+        len
+    }
+
+    #[test]
+    fn encoding() {
+        let mut buf = [0u8; 16];
+        let len = encode(
+            &Samples16 {
+                items: [7, 7, 7, 9],
+            },
+            &mut buf,
+        );
+        assert_eq!(&buf[..len.unwrap()], &[2, 0, 7, 0, 3, 9, 0, 1]);
+
+        assert_eq!(round_trip([]), 2);
+        assert_eq!(round_trip([3; 255]), 2 + 3);
+        assert_eq!(round_trip([3; 256]), 2 + 2 * 3);
+        assert_eq!(round_trip([3; 600]), 2 + 3 * 3);
+    }
+
+    #[test]
+    fn errors() {
+        // the wrong number of items, and an empty run
+        assert_eq!(
+            decode::<Samples16<2>>(&[1, 0, 7, 0, 3]),
+            Err(Error::LengthExceeded)
+        );
+        assert_eq!(
+            decode::<Samples16<4>>(&[1, 0, 7, 0, 3]),
+            Err(Error::Custom)
+        );
+        assert_eq!(
+            decode::<Samples16<0>>(&[1, 0, 7, 0, 0]),
+            Err(Error::Custom)
+        );
+    }
+
+    #[test]
+    fn into_slice() {
+        let mut buf = [0u8; 16];
+        let len = encode(
+            &Samples16 {
+                items: [7, 7, 7, 9],
+            },
+            &mut buf,
+        )
+        .unwrap();
+
+        let mut items = [0u16; 6];
+        let seed = IntoSlice(&mut items);
+        assert_eq!(seed.deserialize(&mut Fermion::new(&buf[..len])), Ok(4));
+        assert_eq!(items, [7, 7, 7, 9, 0, 0]);
+
+        let mut items = [0u16; 3];
+        let seed = IntoSlice(&mut items);
+        assert_eq!(
+            seed.deserialize(&mut Fermion::new(&buf[..len])),
+            Err(Error::LengthExceeded)
+        );
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn heapless_vec() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Log {
+            #[serde(with = "crate::with::delta")]
+            timestamps: heapless::Vec<u32, 8>,
+            #[serde(with = "super")]
+            levels: heapless::Vec<u8, 8>,
+        }
+
+        let log = Log {
+            timestamps: heapless::Vec::from_slice(&[10, 20, 30]).unwrap(),
+            levels: heapless::Vec::from_slice(&[1, 1, 2]).unwrap(),
+        };
+        let mut buf = [0u8; 32];
+        let len = encode(&log, &mut buf).unwrap();
+        assert_eq!(len, 2 + 3 + 2 + 2 * 2);
+        assert_eq!(decode(&buf[..len]), Ok(log));
+
+        // more items than the capacity
+        assert_eq!(
+            decode::<Log>(&[3, 0, 20, 20, 20, 1, 0, 1, 9]),
+            Err(Error::LengthExceeded)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary(
+            items in any::<[u16; 32]>(),
+            steps in any::<[u8; 32]>(),
+        ) {
+            round_trip(items);
+
+            // few distinct values, repeating for a while
+            let mut stepped = [0u16; 32];
+            for i in 1..32 {
+                stepped[i] = stepped[i - 1] + (steps[i] % 8 == 0) as u16;
+            }
+            round_trip(stepped);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Zero-byte packing, compressing the runs of zero bytes left by wide
+//! integers, the way Cap'n Proto does.
+//!
+//! Input is split in 8 byte words, the last one padded with zeros. Each word
+//! is written as a tag byte with bit `i` set if its byte `i` is non-zero,
+//! followed by the non-zero bytes. A `0x00` tag is followed by the number of
+//! zero words after it, and a `0xff` tag by the number of words after it
+//! copied as they are, as input with few zeros doesn't pack.
+
+use serde::{Deserialize, Serialize};
+
+use crate::ser::{write_to_slice, Writer};
+use crate::{decode, encode, Error};
+
+const WORD: usize = 8;
+
+/// Returns the word at `at`, padded with zeros past the end of `input`
+fn word(input: &[u8], at: usize) -> [u8; WORD] {
+    let mut word = [0u8; WORD];
+    let bytes = &input[at..input.len().min(at + WORD)];
+    word[..bytes.len()].copy_from_slice(bytes);
+    word
+}
+
+fn zeros(word: &[u8; WORD]) -> usize {
+    word.iter().filter(|b| **b == 0).count()
+}
+
+fn pack<W: Writer + ?Sized>(input: &[u8], out: &mut W) -> Result<(), Error> {
+    let mut at = 0;
+    while at < input.len() {
+        let current = word(input, at);
+        at += WORD;
+
+        let mut tag = 0u8;
+        let mut packed = [0u8; WORD];
+        let mut len = 0;
+        for (i, byte) in current.iter().enumerate() {
+            if *byte != 0 {
+                tag |= 1 << i;
+                packed[len] = *byte;
+                len += 1;
+            }
+        }
+        out.write(&[tag])?;
+        out.write(&packed[..len])?;
+
+        match tag {
+            0 => {
+                let mut run = 0u8;
+                while run < u8::MAX
+                    && at < input.len()
+                    && zeros(&word(input, at)) == WORD
+                {
+                    run += 1;
+                    at += WORD;
+                }
+                out.write(&[run])?;
+            }
+            0xff => {
+                // words with a single zero would only save that byte
+                let mut run = 0u8;
+                while run < u8::MAX
+                    && at + run as usize * WORD < input.len()
+                    && zeros(&word(input, at + run as usize * WORD)) <= 1
+                {
+                    run += 1;
+                }
+                out.write(&[run])?;
+                for _ in 0..run {
+                    out.write(&word(input, at))?;
+                    at += WORD;
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Takes the next `len` bytes of packed input
+fn take<'a>(
+    input: &'a [u8],
+    at: &mut usize,
+    len: usize,
+) -> Result<&'a [u8], Error> {
+    let bytes = input
+        .get(*at..*at + len)
+        .ok_or(Error::InvalidRepresentation)?;
+    *at += len;
+    Ok(bytes)
+}
+
+fn unpack<W: Writer + ?Sized>(input: &[u8], out: &mut W) -> Result<(), Error> {
+    let mut at = 0;
+    while at < input.len() {
+        let tag = input[at];
+        at += 1;
+
+        let mut current = [0u8; WORD];
+        for (i, byte) in current.iter_mut().enumerate() {
+            if tag & 1 << i != 0 {
+                *byte = take(input, &mut at, 1)?[0];
+            }
+        }
+        out.write(&current)?;
+
+        match tag {
+            0 => {
+                for _ in 0..take(input, &mut at, 1)?[0] {
+                    out.write(&[0; WORD])?;
+                }
+            }
+            0xff => {
+                let run = take(input, &mut at, 1)?[0] as usize;
+                out.write(take(input, &mut at, run * WORD)?)?;
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Packs the zero bytes of `input` into `out`
+///
+/// Returns the number of bytes written. In the worst case the output is a
+/// sixteenth longer than the input padded to whole words, plus 2 bytes.
+///
+/// ```
+/// let mut encoded = [0u8; 24];
+/// fermion::encode(&(5u64, 7u128), &mut encoded).unwrap();
+///
+/// let mut packed = [0u8; 8];
+/// assert_eq!(fermion::pack_zeros(&encoded, &mut packed), Ok(6));
+/// assert_eq!(&packed[..6], &[0x01, 5, 0x01, 7, 0x00, 0]);
+/// ```
+pub fn pack_zeros(input: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    write_to_slice(|writer| pack(input, writer), out)
+}
+
+/// Unpacks input packed by `pack_zeros` into `out`
+///
+/// Returns the number of bytes written, which includes the padding of the
+/// last word. Fails with `Error::InvalidRepresentation` on truncated input.
+pub fn unpack_zeros(input: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    write_to_slice(|writer| unpack(input, writer), out)
+}
+
+/// Encodes a value into `scratch`, and packs it into `out`
+///
+/// Returns the number of bytes written to `out`.
+pub fn encode_zero_packed<T: Serialize>(
+    value: &T,
+    scratch: &mut [u8],
+    out: &mut [u8],
+) -> Result<usize, Error> {
+    let len = encode(value, scratch)?;
+    pack_zeros(&scratch[..len], out)
+}
+
+/// Unpacks a value packed by `encode_zero_packed` into `buf`, and decodes it
+///
+/// Borrowed values point into `buf`.
+pub fn decode_zero_packed<'de, T: Deserialize<'de>>(
+    packed: &[u8],
+    buf: &'de mut [u8],
+) -> Result<T, Error> {
+    let len = unpack_zeros(packed, buf)?;
+    decode(&buf[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    fn round_trip(input: &[u8]) -> usize {
+        let mut packed = [0u8; 4096];
+        let len = pack_zeros(input, &mut packed).unwrap();
+        let mut unpacked = [0xaau8; 4096];
+        let unpacked_len = unpack_zeros(&packed[..len], &mut unpacked).unwrap();
+        assert_eq!(unpacked_len, input.len().div_ceil(8) * 8);
+        assert_eq!(&unpacked[..input.len()], input);
+        assert!(unpacked[input.len()..unpacked_len].iter().all(|b| *b == 0));
+        len
+    }
+
+    #[test]
+    fn zero_runs() {
+        // a tag and a count for up to 256 zero words
+        assert_eq!(round_trip(&[0; 8]), 2);
+        assert_eq!(round_trip(&[0; 256 * 8]), 2);
+        assert_eq!(round_trip(&[0; 257 * 8]), 4);
+        assert_eq!(round_trip(&[0; 3]), 2);
+    }
+
+    #[test]
+    fn raw_runs() {
+        let mut input = [0x11u8; 40];
+        // the fourth word has a zero, the fifth two
+        input[27] = 0;
+        input[33] = 0;
+        input[34] = 0;
+        let mut packed = [0u8; 64];
+        let len = pack_zeros(&input, &mut packed).unwrap();
+        // tag, 8 bytes and 3 raw words, then a tag and 6 bytes
+        assert_eq!(len, 1 + 8 + 1 + 24 + 1 + 6);
+        assert_eq!(packed[9], 3);
+        assert_eq!(round_trip(&input), len);
+
+        // 255 raw words at most follow a tag
+        assert_eq!(round_trip(&[1; 300 * 8]), 300 * 8 + 4);
+    }
+
+    #[test]
+    fn worst_case() {
+        // a word without zeros, then one with too many to follow it raw
+        let input = [[1, 1, 1, 1, 1, 1, 1, 1], [0, 0, 1, 1, 1, 1, 1, 1]];
+        assert_eq!(round_trip(input.as_flattened()), 10 + 7);
+    }
+
+    #[test]
+    fn errors() {
+        let input = [0x11u8; 16];
+        assert_eq!(
+            pack_zeros(&input, &mut [0u8; 8]),
+            Err(Error::OutOfSpace {
+                needed: 18,
+                available: 8
+            })
+        );
+
+        let packed = [0x00, 3];
+        assert_eq!(
+            unpack_zeros(&packed, &mut [0u8; 16]),
+            Err(Error::OutOfSpace {
+                needed: 32,
+                available: 16
+            })
+        );
+
+        // a word missing its bytes, and a raw run missing a word
+        assert_eq!(
+            unpack_zeros(&[0x03, 1], &mut [0u8; 16]),
+            Err(Error::InvalidRepresentation)
+        );
+        let mut packed = [0x11u8; 18];
+        packed[0] = 0xff;
+        packed[9] = 2;
+        assert_eq!(
+            unpack_zeros(&packed, &mut [0u8; 32]),
+            Err(Error::InvalidRepresentation)
+        );
+    }
+
+    #[test]
+    fn counters() {
+        type Counters = (u64, u128, [u32; 4], &'static str);
+        let value: Counters = (1_000, 42, [1, 0, 70_000, 3], "ok");
+
+        let mut scratch = [0u8; 64];
+        let mut packed = [0u8; 64];
+        let len =
+            encode_zero_packed(&value, &mut scratch, &mut packed).unwrap();
+        // 44 bytes in 6 words, the third one all zeros
+        assert_eq!(len, 3 + 2 + 2 + 2 + 5 + 4);
+
+        let mut buf = [0u8; 64];
+        assert_eq!(decode_zero_packed(&packed[..len], &mut buf), Ok(value));
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary(
+            input in proptest::collection::vec(
+                prop_oneof![Just(0u8), any::<u8>()],
+                0..512,
+            )
+        ) {
+            let padded = input.len().div_ceil(8) * 8;
+            prop_assert!(round_trip(&input) <= padded + padded / 16 + 2);
+        }
+    }
+}